@@ -0,0 +1,1105 @@
+// Copyright 2020 Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Code blocks.
+
+use pulldown_cmark::{CodeBlockKind, Event, Tag};
+use std::collections::HashMap;
+use std::path::{Component, Path};
+use std::str::FromStr;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use unicode_width::UnicodeWidthStr;
+
+/// What to do with lines of code longer than the terminal is wide.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CodeOverflow {
+    /// Wrap long lines, and mark the continuation.
+    Wrap,
+    /// Cut long lines off, and end them with an ellipsis.
+    Truncate,
+    /// Leave long lines as they are, and let the terminal wrap them.
+    None,
+}
+
+impl Default for CodeOverflow {
+    /// Leave long lines as they are.
+    fn default() -> CodeOverflow {
+        CodeOverflow::None
+    }
+}
+
+impl FromStr for CodeOverflow {
+    type Err = String;
+
+    /// Parse a code overflow policy from its lowercase name, e.g. `wrap`.
+    fn from_str(s: &str) -> Result<CodeOverflow, String> {
+        match s {
+            "wrap" => Ok(CodeOverflow::Wrap),
+            "truncate" => Ok(CodeOverflow::Truncate),
+            "none" => Ok(CodeOverflow::None),
+            _ => Err(format!("Unknown code overflow: {}", s)),
+        }
+    }
+}
+
+/// Information about a fenced code block, from its info string.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CodeBlockInfo<'a> {
+    /// The language of the code block, if any.
+    pub language: Option<&'a str>,
+    /// The language of code inside the code block, from the second word.
+    ///
+    /// Like `rust` in `diff rust`, for the code in a diff.
+    pub inner_language: Option<&'a str>,
+    /// Whether to number lines, if given.
+    ///
+    /// `linenos` numbers lines, `nolinenos` does not.
+    pub line_numbers: Option<bool>,
+    /// The number of the first line, from `start=N`.
+    pub start_line: Option<u64>,
+    /// The file to tangle the code block to, from `file=PATH`.
+    pub file: Option<&'a str>,
+}
+
+impl<'a> CodeBlockInfo<'a> {
+    /// Parse the `info` string of a fenced code block.
+    ///
+    /// Split `info` into words at commas, whitespace and braces, like
+    /// `rust,linenos,start=10`, `rust ignore` or Pandoc's `{.python
+    /// .numberLines startFrom="10"}`.  The first word is the language, unless
+    /// it's an attribute; in braces the first class like `.python` is the
+    /// language.  A second word after the language is the inner language.
+    /// Ignore unknown attributes.
+    pub fn parse(info: &'a str) -> CodeBlockInfo<'a> {
+        let braced = info.trim_start().starts_with('{');
+        let words = info
+            .split(|c: char| c == ',' || c == '{' || c == '}' || c.is_whitespace())
+            .filter(|word| !word.is_empty());
+        let mut code_block_info = CodeBlockInfo::default();
+        for (index, word) in words.enumerate() {
+            match word {
+                "linenos" | ".numberLines" | ".number-lines" => {
+                    code_block_info.line_numbers = Some(true)
+                }
+                "nolinenos" => code_block_info.line_numbers = Some(false),
+                _ => {
+                    if let Some(start) = attribute_value(word, &["start", "startFrom"]) {
+                        code_block_info.start_line = start.parse().ok();
+                    } else if let Some(file) = attribute_value(word, &["file"]) {
+                        code_block_info.file = Some(file).filter(|file| !file.is_empty());
+                    } else if code_block_info.language.is_none() {
+                        if let Some(class) = word.strip_prefix('.') {
+                            code_block_info.language = Some(class);
+                        } else if index == 0 && !braced {
+                            code_block_info.language = Some(word);
+                        }
+                    } else if index == 1 && !braced && !word.contains('=') {
+                        code_block_info.inner_language = Some(word);
+                    }
+                }
+            }
+        }
+        code_block_info
+    }
+
+    /// The number of the first line, if we number lines.
+    ///
+    /// Number lines if this code block asks for it with `linenos` or
+    /// `start=N`, or if `default` is set and the code block doesn't ask for
+    /// `nolinenos`.
+    pub fn first_line_number(&self, default: bool) -> Option<u64> {
+        let numbered = self
+            .line_numbers
+            .unwrap_or(default || self.start_line.is_some());
+        if numbered {
+            Some(self.start_line.unwrap_or(1))
+        } else {
+            None
+        }
+    }
+}
+
+/// A code block of a document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeBlock {
+    /// Whether the code block is fenced rather than indented.
+    pub fenced: bool,
+    /// The info string of the code block.
+    ///
+    /// Empty for indented code blocks, and fenced code blocks without info.
+    pub info: String,
+    /// The contents of the code block, as written in the document.
+    pub contents: String,
+}
+
+/// Get all code blocks in `events`, in order.
+pub fn code_blocks<'a, I>(events: I) -> Vec<CodeBlock>
+where
+    I: IntoIterator<Item = &'a Event<'a>>,
+{
+    let mut blocks = Vec::new();
+    let mut current: Option<CodeBlock> = None;
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let (fenced, info) = match kind {
+                    CodeBlockKind::Indented => (false, String::new()),
+                    CodeBlockKind::Fenced(info) => (true, info.to_string()),
+                };
+                current = Some(CodeBlock {
+                    fenced,
+                    info,
+                    contents: String::new(),
+                });
+            }
+            Event::Text(text) => {
+                if let Some(ref mut block) = current {
+                    block.contents.push_str(text);
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) => blocks.extend(current.take()),
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Tangle code `blocks` into files.
+///
+/// Collect the contents of all code blocks with a `file=PATH` attribute in
+/// their info string, per `PATH`.  Join the contents of code blocks for the
+/// same file in order.  Return all files with their contents, in the order
+/// they first appear in.
+///
+/// Fail if any `PATH` is absolute, leaves its directory with `..`, or does not
+/// name a file, because documents must not write files anywhere else than
+/// beneath their own directory.
+pub fn tangle(blocks: &[CodeBlock]) -> Result<Vec<(&str, String)>, String> {
+    let mut files: Vec<(&str, String)> = Vec::new();
+    for block in blocks.iter().filter(|block| block.fenced) {
+        if let Some(file) = CodeBlockInfo::parse(&block.info).file {
+            if !is_relative_path_beneath(file) {
+                return Err(format!(
+                    "Refusing to tangle code block to {}: Not a relative path beneath the document",
+                    file
+                ));
+            }
+            match files.iter_mut().find(|(path, _)| *path == file) {
+                Some((_, contents)) => contents.push_str(&block.contents),
+                None => files.push((file, block.contents.clone())),
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Whether `path` is relative and names a file beneath its base directory.
+///
+/// Reject absolute paths, paths with a Windows prefix like `C:`, paths with
+/// `..`, and paths which end in a directory like `.` or `conf/`.
+fn is_relative_path_beneath(path: &str) -> bool {
+    let components = Path::new(path).components().collect::<Vec<_>>();
+    // `components` drops a trailing `.` or separator, so look at the raw name
+    let name = path.rsplit(std::path::is_separator).next().unwrap_or("");
+    matches!(components.last(), Some(Component::Normal(_)))
+        && !name.is_empty()
+        && name != "."
+        && components
+            .iter()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// The value of `word` if it's an attribute with any of the given `keys`.
+///
+/// Strip quotes around the value, like in `startFrom="10"`.
+fn attribute_value<'a>(word: &'a str, keys: &[&str]) -> Option<&'a str> {
+    let (key, value) = word.split_at(word.find('=')?);
+    if keys.contains(&key) {
+        Some(value[1..].trim_matches('"'))
+    } else {
+        None
+    }
+}
+
+/// Aliases for languages of code blocks which syntect doesn't know.
+///
+/// Map common names of languages in code blocks to a language token or
+/// syntax name of syntect's default syntaxes.
+const DEFAULT_SYNTAX_ALIASES: &[(&str, &str)] = &[
+    ("console", "bash"),
+    ("shell", "bash"),
+    ("shell-session", "bash"),
+    ("shellsession", "bash"),
+    ("sh-session", "bash"),
+    ("ksh", "bash"),
+    ("jsonc", "json"),
+    ("json5", "json"),
+    ("ts", "javascript"),
+    ("typescript", "javascript"),
+    ("tsx", "javascript"),
+    ("jsx", "javascript"),
+    ("mjs", "javascript"),
+    ("cjs", "javascript"),
+    ("node", "javascript"),
+    ("nodejs", "javascript"),
+    ("golang", "go"),
+    ("csharp", "c#"),
+    ("objc", "objective-c"),
+    ("elisp", "lisp"),
+    ("emacs-lisp", "lisp"),
+    ("text", "plain text"),
+    ("plaintext", "plain text"),
+];
+
+/// Find the syntax for the `language` of a code block.
+///
+/// Look up `language` in `aliases` first, then in a few default aliases for
+/// common languages, like `console` for shell sessions or `ts` for
+/// TypeScript.  Aliases map to a language token or the name of a syntax in
+/// `syntax_set`.  Fall back to `language` itself.
+pub fn find_syntax<'s>(
+    syntax_set: &'s SyntaxSet,
+    aliases: &HashMap<String, String>,
+    language: &str,
+) -> Option<&'s SyntaxReference> {
+    let find = |token: &str| {
+        syntax_set
+            .find_syntax_by_token(token)
+            .or_else(|| syntax_set.find_syntax_by_name(token))
+    };
+    let alias = aliases
+        .iter()
+        .map(|(alias, target)| (alias.as_str(), target.as_str()))
+        .chain(DEFAULT_SYNTAX_ALIASES.iter().copied())
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(language));
+    alias
+        .and_then(|(_, target)| find(target))
+        .or_else(|| find(language))
+}
+
+/// The kind of a line in a unified diff.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DiffLine<'a> {
+    /// A header line, like `diff --git`, `--- a/…` or `@@ -1,2 +1,3 @@`.
+    ///
+    /// For `+++ b/…` the path of the new file, if any.
+    Header(Option<&'a str>),
+    /// A line added, starting with `+`.
+    Added,
+    /// A line removed, starting with `-`.
+    Removed,
+    /// A line of context, starting with a space.
+    Context,
+    /// Any other line, e.g. a commit message.
+    Other,
+}
+
+/// Classify the lines of a unified diff.
+///
+/// Count the lines of each hunk from its header, to tell removed lines like
+/// `--- x` from headers.  Outside of hunks, e.g. in handwritten diffs without
+/// headers, take all lines starting with `+`, `-` or a space as changes.
+#[derive(Debug, Default)]
+pub struct DiffLines {
+    /// The number of old lines left in the current hunk.
+    old: usize,
+    /// The number of new lines left in the current hunk.
+    new: usize,
+}
+
+impl DiffLines {
+    /// Classify the next `line` of a diff.
+    pub fn classify<'l>(&mut self, line: &'l str) -> DiffLine<'l> {
+        let line = line.trim_end_matches(&['\n', '\r'][..]);
+        if 0 < self.old || 0 < self.new {
+            let kind = match line.chars().next() {
+                Some('+') => DiffLine::Added,
+                Some('-') => DiffLine::Removed,
+                Some(' ') | None => DiffLine::Context,
+                // E.g. "\ No newline at end of file"
+                _ => return DiffLine::Other,
+            };
+            if kind != DiffLine::Added {
+                self.old = self.old.saturating_sub(1);
+            }
+            if kind != DiffLine::Removed {
+                self.new = self.new.saturating_sub(1);
+            }
+            kind
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            let path = path.split('\t').next().unwrap_or(path).trim();
+            let path = path.strip_prefix("b/").unwrap_or(path);
+            DiffLine::Header(Some(path).filter(|path| *path != "/dev/null"))
+        } else if let Some(ranges) = line.strip_prefix("@@ ") {
+            // Count the lines from ranges like "-1,3 +1,4", where the count
+            // defaults to 1
+            let count = |prefix: char| {
+                ranges
+                    .split_whitespace()
+                    .find_map(|range| range.strip_prefix(prefix))
+                    .map(|range| match range.find(',') {
+                        Some(index) => range[index + 1..].parse().unwrap_or(0),
+                        None => 1,
+                    })
+                    .unwrap_or(0)
+            };
+            self.old = count('-');
+            self.new = count('+');
+            DiffLine::Header(None)
+        } else if line.starts_with("--- ") || line.starts_with("diff ") {
+            DiffLine::Header(None)
+        } else {
+            match line.chars().next() {
+                Some('+') => DiffLine::Added,
+                Some('-') => DiffLine::Removed,
+                Some(' ') => DiffLine::Context,
+                _ => DiffLine::Other,
+            }
+        }
+    }
+}
+
+/// Find the syntax for the file at `path`, by its extension or its name.
+pub fn find_syntax_for_path<'s>(
+    syntax_set: &'s SyntaxSet,
+    path: &str,
+) -> Option<&'s SyntaxReference> {
+    let path = Path::new(path);
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(|extension| syntax_set.find_syntax_by_extension(extension))
+        .or_else(|| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| syntax_set.find_syntax_by_extension(name))
+        })
+}
+
+/// Format the contents of code blocks with data, like CSV or JSON.
+///
+/// Turn CSV and TSV into aligned tables, and re-indent JSON, in all fenced
+/// code blocks in `events` with `csv`, `tsv` or `json` as language.  Leave
+/// code blocks with invalid data as they are.
+pub fn format_data_blocks(events: Vec<Event>) -> Vec<Event> {
+    let mut formatted = Vec::with_capacity(events.len());
+    // The language of the current data block, and its text events
+    let mut data_block: Option<(String, Vec<Event>)> = None;
+    for event in events {
+        match (event, &mut data_block) {
+            (Event::Text(text), Some((_, texts))) => texts.push(Event::Text(text)),
+            (event @ Event::End(Tag::CodeBlock(_)), Some(_)) => {
+                let (language, texts) = data_block.take().unwrap();
+                let contents = texts
+                    .iter()
+                    .map(|text| match text {
+                        Event::Text(text) => text.as_ref(),
+                        _ => "",
+                    })
+                    .collect::<String>();
+                match format_data(&language, &contents) {
+                    Some(data) => formatted.push(Event::Text(data.into())),
+                    None => formatted.extend(texts),
+                }
+                formatted.push(event);
+            }
+            (event, _) => {
+                if let Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) = event {
+                    data_block = CodeBlockInfo::parse(info)
+                        .language
+                        .map(str::to_ascii_lowercase)
+                        .filter(|language| ["csv", "tsv", "json"].contains(&language.as_str()))
+                        .map(|language| (language, Vec::new()));
+                }
+                formatted.push(event);
+            }
+        }
+    }
+    formatted
+}
+
+/// Format `contents` of a code block with data in the given `language`.
+///
+/// Return `None` if `contents` are no valid data in `language`.
+fn format_data(language: &str, contents: &str) -> Option<String> {
+    match language {
+        "csv" => parse_delimited(contents, ',').map(|rows| format_table(&rows)),
+        "tsv" => parse_delimited(contents, '\t').map(|rows| format_table(&rows)),
+        "json" => reindent_json(contents),
+        _ => None,
+    }
+}
+
+/// Parse `contents` as rows of fields separated by `delimiter`.
+///
+/// Support quoted fields for commas, like in CSV.  Return `None` if
+/// `contents` are empty, have an unterminated quote, or rows with different
+/// numbers of fields.
+fn parse_delimited(contents: &str, delimiter: char) -> Option<Vec<Vec<String>>> {
+    let quotes = delimiter == ',';
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if quotes && field.is_empty() => quoted = true,
+            '\n' | '\r' if quoted => field.push(' '),
+            '\r' => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c if c == delimiter && !quoted => row.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    let columns = rows.first()?.len();
+    if rows.iter().all(|row| row.len() == columns) {
+        Some(rows)
+    } else {
+        None
+    }
+}
+
+/// Format `rows` as a table with aligned columns.
+///
+/// Take the first row as header, and right-align columns with only numbers.
+fn format_table(rows: &[Vec<String>]) -> String {
+    let width = |cell: &String| cell.width();
+    let columns = rows[0].len();
+    let widths = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .map(|row| width(&row[column]))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let numeric = (0..columns)
+        .map(|column| {
+            rows.len() > 1
+                && rows[1..]
+                    .iter()
+                    .all(|row| row[column].trim().parse::<f64>().is_ok())
+        })
+        .collect::<Vec<_>>();
+    let mut table = String::new();
+    for (index, row) in rows.iter().enumerate() {
+        let cells = row
+            .iter()
+            .enumerate()
+            .map(|(column, cell)| {
+                let padding = " ".repeat(widths[column] - width(cell));
+                if numeric[column] && index > 0 {
+                    format!("{}{}", padding, cell)
+                } else {
+                    format!("{}{}", cell, padding)
+                }
+            })
+            .collect::<Vec<_>>();
+        table.push_str(cells.join(" \u{2502} ").trim_end());
+        table.push('\n');
+        if index == 0 && rows.len() > 1 {
+            let rules = widths
+                .iter()
+                .map(|width| "\u{2500}".repeat(*width))
+                .collect::<Vec<_>>();
+            table.push_str(&rules.join("\u{2500}\u{253c}\u{2500}"));
+            table.push('\n');
+        }
+    }
+    table
+}
+
+/// Re-indent JSON in `contents` with two spaces.
+///
+/// Keep the order of keys and the text of all values.  Return `None` if
+/// `contents` are no valid JSON.
+fn reindent_json(contents: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(contents).ok()?;
+    let mut json = String::with_capacity(contents.len());
+    let mut level = 0;
+    let mut in_string = false;
+    let mut chars = contents.trim().chars().peekable();
+    let newline = |json: &mut String, level: usize| {
+        json.push('\n');
+        json.push_str(&"  ".repeat(level));
+    };
+    while let Some(c) = chars.next() {
+        if in_string {
+            json.push(c);
+            match c {
+                '\\' => json.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                json.push(c);
+            }
+            '{' | '[' => {
+                json.push(c);
+                while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+                    chars.next();
+                }
+                if chars.peek() == Some(&'}') || chars.peek() == Some(&']') {
+                    json.extend(chars.next());
+                } else {
+                    level += 1;
+                    newline(&mut json, level);
+                }
+            }
+            '}' | ']' => {
+                level -= 1;
+                newline(&mut json, level);
+                json.push(c);
+            }
+            ',' => {
+                json.push(c);
+                newline(&mut json, level);
+            }
+            ':' => json.push_str(": "),
+            c if c.is_whitespace() => {}
+            c => json.push(c),
+        }
+    }
+    json.push('\n');
+    Some(json)
+}
+
+/// Prefixes of lines typical for some languages, to guess the language of code.
+///
+/// Map a language token to prefixes of lines, without leading whitespace.
+const LANGUAGE_HEURISTICS: &[(&str, &[&str])] = &[
+    ("diff", &["diff --git ", "--- a/", "+++ b/", "@@ -"]),
+    (
+        "rust",
+        &[
+            "fn ",
+            "pub fn ",
+            "let mut ",
+            "use std::",
+            "impl ",
+            "#[derive(",
+            "pub struct ",
+            "println!(",
+        ],
+    ),
+    (
+        "python",
+        &[
+            "def ",
+            "import ",
+            "from ",
+            "elif ",
+            "print(",
+            "if __name__ ",
+            "self.",
+        ],
+    ),
+    ("go", &["package ", "func ", "import (", "fmt."]),
+    (
+        "java",
+        &[
+            "public class ",
+            "public static ",
+            "private ",
+            "import java.",
+            "System.out.",
+        ],
+    ),
+    (
+        "cpp",
+        &[
+            "#include <iostream>",
+            "std::",
+            "template <",
+            "template<",
+            "namespace ",
+            "using namespace ",
+        ],
+    ),
+    (
+        "c",
+        &["#include ", "int main(", "printf(", "#define ", "typedef "],
+    ),
+    (
+        "javascript",
+        &[
+            "function ",
+            "const ",
+            "var ",
+            "console.log(",
+            "import {",
+            "module.exports",
+            "require(",
+        ],
+    ),
+    (
+        "sql",
+        &[
+            "SELECT ",
+            "INSERT INTO ",
+            "CREATE TABLE ",
+            "UPDATE ",
+            "DELETE FROM ",
+            "FROM ",
+            "WHERE ",
+        ],
+    ),
+    (
+        "html",
+        &[
+            "<!DOCTYPE html",
+            "<html",
+            "<head",
+            "<body",
+            "<div",
+            "<p>",
+            "<script",
+        ],
+    ),
+    (
+        "bash",
+        &[
+            "$ ", "sudo ", "export ", "echo ", "cd ", "apt-get ", "brew ", "git ", "cargo ",
+            "npm ", "if [",
+        ],
+    ),
+];
+
+/// Guess the syntax of code from its `contents`.
+///
+/// Try the interpreter of a shebang line first, then the first line patterns
+/// of the syntaxes in `syntax_set`, e.g. for `<?xml`, then valid JSON, and
+/// finally guess the language which has most lines with typical prefixes.
+pub fn guess_syntax<'s>(syntax_set: &'s SyntaxSet, contents: &str) -> Option<&'s SyntaxReference> {
+    let first_line = contents.lines().find(|line| !line.trim().is_empty())?;
+    if let Some(interpreter) = shebang_interpreter(first_line) {
+        let no_aliases = HashMap::new();
+        let syntax = find_syntax(syntax_set, &no_aliases, interpreter).or_else(|| {
+            // Try without version, e.g. python for python3.7
+            let name = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+            find_syntax(syntax_set, &no_aliases, name)
+        });
+        if syntax.is_some() {
+            return syntax;
+        }
+    }
+    if let Some(syntax) = syntax_set.find_syntax_by_first_line(first_line) {
+        return Some(syntax);
+    }
+    let trimmed = contents.trim();
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return syntax_set.find_syntax_by_token("json");
+    }
+    let score = |prefixes: &[&str]| {
+        contents
+            .lines()
+            .map(str::trim_start)
+            .filter(|line| prefixes.iter().any(|prefix| line.starts_with(prefix)))
+            .count()
+    };
+    LANGUAGE_HEURISTICS
+        .iter()
+        .map(|(token, prefixes)| (token, score(prefixes)))
+        .filter(|(_, score)| 0 < *score)
+        // Take the first of the best languages
+        .fold(
+            None,
+            |best: Option<(&&str, usize)>, (token, score)| match best {
+                Some((_, best_score)) if score <= best_score => best,
+                _ => Some((token, score)),
+            },
+        )
+        .and_then(|(token, _)| syntax_set.find_syntax_by_token(token))
+}
+
+/// The interpreter of a shebang `line`, if any.
+///
+/// Skip `env` and its options, e.g. `python3` for `#!/usr/bin/env -S python3 -u`.
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        words.find(|word| !word.starts_with('-') && !word.contains('='))
+    } else {
+        Some(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn all_code_blocks() {
+        use pulldown_cmark::Parser;
+
+        let events = Parser::new(
+            "```rust,linenos\nfn main() {}\n```\n\nText `code`\n\n    indented\n    code\n",
+        )
+        .collect::<Vec<_>>();
+        assert_eq!(
+            code_blocks(&events),
+            vec![
+                CodeBlock {
+                    fenced: true,
+                    info: "rust,linenos".to_string(),
+                    contents: "fn main() {}\n".to_string(),
+                },
+                CodeBlock {
+                    fenced: false,
+                    info: String::new(),
+                    contents: "indented\ncode\n".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tangle_code_blocks() {
+        use pulldown_cmark::Parser;
+
+        let events = Parser::new(
+            "```sh,file=setup.sh\necho 1\n```\n
+```toml,file=config.toml\n[a]\n```\n
+```sh\nnot tangled\n```\n
+```sh,file=setup.sh\necho 2\n```\n",
+        )
+        .collect::<Vec<_>>();
+        let blocks = code_blocks(&events);
+        assert_eq!(
+            tangle(&blocks),
+            Ok(vec![
+                ("setup.sh", "echo 1\necho 2\n".to_string()),
+                ("config.toml", "[a]\n".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn tangle_only_beneath_the_document() {
+        use pulldown_cmark::Parser;
+
+        let tangle_to = |path: &str| {
+            let markdown = format!("```sh,file={}\necho 1\n```\n", path);
+            let events = Parser::new(&markdown).collect::<Vec<_>>();
+            tangle(&code_blocks(&events)).map(|files| {
+                files
+                    .into_iter()
+                    .map(|(path, _)| path.to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            tangle_to("conf/./app.toml"),
+            Ok(vec!["conf/./app.toml".to_string()])
+        );
+        for path in &[
+            "/home/u/.bashrc",
+            "../../.ssh/authorized_keys",
+            "conf/../../outside",
+            "..",
+            ".",
+            "./",
+            "conf/",
+            "conf/.",
+        ] {
+            assert_eq!(
+                tangle_to(path),
+                Err(format!(
+                    "Refusing to tangle code block to {}: Not a relative path beneath the document",
+                    path
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn parse_code_overflow() {
+        assert_eq!("wrap".parse(), Ok(CodeOverflow::Wrap));
+        assert_eq!("truncate".parse(), Ok(CodeOverflow::Truncate));
+        assert_eq!("none".parse(), Ok(CodeOverflow::None));
+        assert!("cut".parse::<CodeOverflow>().is_err());
+    }
+
+    #[test]
+    fn parse_info_string() {
+        assert_eq!(
+            CodeBlockInfo::parse("rust,linenos,start=10"),
+            CodeBlockInfo {
+                language: Some("rust"),
+                inner_language: None,
+                line_numbers: Some(true),
+                start_line: Some(10),
+                file: None,
+            }
+        );
+        assert_eq!(
+            CodeBlockInfo::parse("ruby startline=3 $%@#$"),
+            CodeBlockInfo {
+                language: Some("ruby"),
+                inner_language: None,
+                line_numbers: None,
+                start_line: None,
+                file: None,
+            }
+        );
+        assert_eq!(
+            CodeBlockInfo::parse("linenos,start=5"),
+            CodeBlockInfo {
+                language: None,
+                inner_language: None,
+                line_numbers: Some(true),
+                start_line: Some(5),
+                file: None,
+            }
+        );
+        assert_eq!(
+            CodeBlockInfo::parse("diff rust,linenos"),
+            CodeBlockInfo {
+                language: Some("diff"),
+                inner_language: Some("rust"),
+                line_numbers: Some(true),
+                start_line: None,
+                file: None,
+            }
+        );
+        assert_eq!(
+            CodeBlockInfo::parse("python {linenos file=hello.py}"),
+            CodeBlockInfo {
+                language: Some("python"),
+                inner_language: None,
+                line_numbers: Some(true),
+                start_line: None,
+                file: Some("hello.py"),
+            }
+        );
+        assert_eq!(
+            CodeBlockInfo::parse("{.haskell .numberLines startFrom=\"100\"}"),
+            CodeBlockInfo {
+                language: Some("haskell"),
+                inner_language: None,
+                line_numbers: Some(true),
+                start_line: Some(100),
+                file: None,
+            }
+        );
+        assert_eq!(
+            CodeBlockInfo::parse("{#example .python}"),
+            CodeBlockInfo {
+                language: Some("python"),
+                ..CodeBlockInfo::default()
+            }
+        );
+        assert_eq!(CodeBlockInfo::parse(""), CodeBlockInfo::default());
+    }
+
+    #[test]
+    fn find_syntax_with_aliases() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let find = |aliases: &HashMap<String, String>, language| {
+            find_syntax(&syntax_set, aliases, language).map(|syntax| syntax.name.as_str())
+        };
+        let no_aliases = HashMap::new();
+        assert_eq!(find(&no_aliases, "rust"), Some("Rust"));
+        assert_eq!(
+            find(&no_aliases, "console"),
+            Some("Bourne Again Shell (bash)")
+        );
+        assert_eq!(find(&no_aliases, "jsonc"), Some("JSON"));
+        assert_eq!(find(&no_aliases, "TS"), Some("JavaScript"));
+        assert_eq!(find(&no_aliases, "no-such-language"), None);
+
+        let mut aliases = HashMap::new();
+        aliases.insert("console".to_string(), "Plain Text".to_string());
+        aliases.insert("rust".to_string(), "no-such-language".to_string());
+        assert_eq!(find(&aliases, "console"), Some("Plain Text"));
+        assert_eq!(find(&aliases, "rust"), Some("Rust"));
+    }
+
+    #[test]
+    fn first_line_number() {
+        assert_eq!(CodeBlockInfo::parse("rust").first_line_number(false), None);
+        assert_eq!(
+            CodeBlockInfo::parse("rust").first_line_number(true),
+            Some(1)
+        );
+        assert_eq!(
+            CodeBlockInfo::parse("rust,nolinenos").first_line_number(true),
+            None
+        );
+        assert_eq!(
+            CodeBlockInfo::parse("rust,start=14").first_line_number(false),
+            Some(14)
+        );
+    }
+
+    #[test]
+    fn interpreter_of_shebang() {
+        assert_eq!(shebang_interpreter("#!/bin/sh"), Some("sh"));
+        assert_eq!(
+            shebang_interpreter("#!/usr/bin/env python3"),
+            Some("python3")
+        );
+        assert_eq!(
+            shebang_interpreter("#!/usr/bin/env -S LANG=C ruby -w"),
+            Some("ruby")
+        );
+        assert_eq!(shebang_interpreter("# comment"), None);
+    }
+
+    #[test]
+    fn guess_syntax_of_code() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let guess =
+            |contents| guess_syntax(&syntax_set, contents).map(|syntax| syntax.name.as_str());
+        assert_eq!(
+            guess("\n#!/usr/bin/env python3.8\nprint(1)\n"),
+            Some("Python")
+        );
+        assert_eq!(guess("#!/usr/bin/env node\n"), Some("JavaScript"));
+        assert_eq!(guess("<?xml version=\"1.0\"?>\n<a/>\n"), Some("XML"));
+        assert_eq!(guess("{\"a\": [1, 2]}\n"), Some("JSON"));
+        assert_eq!(
+            guess("fn main() {\n    let mut x = 1;\n    println!(\"{}\", x);\n}\n"),
+            Some("Rust")
+        );
+        assert_eq!(
+            guess("$ cargo build\n$ git status\n"),
+            Some("Bourne Again Shell (bash)")
+        );
+        assert_eq!(
+            guess("SELECT name\nFROM users\nWHERE id = 1;\n"),
+            Some("SQL")
+        );
+        assert_eq!(guess("Just some text\n"), None);
+        assert_eq!(guess(""), None);
+    }
+
+    #[test]
+    fn classify_diff_lines() {
+        let diff = "diff --git a/src/main.rs b/src/main.rs
+index 1234567..89abcde 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,3 +1,3 @@
+ fn main() {
+--- x
++    println!(\"Hello\");
+ }
+\\ No newline at end of file
+Some text
+- removed
++ added
+";
+        let mut lines = DiffLines::default();
+        let kinds = diff
+            .lines()
+            .map(|line| lines.classify(line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                DiffLine::Header(None),
+                DiffLine::Other,
+                DiffLine::Header(None),
+                DiffLine::Header(Some("src/main.rs")),
+                DiffLine::Header(None),
+                DiffLine::Context,
+                DiffLine::Removed,
+                DiffLine::Added,
+                DiffLine::Context,
+                DiffLine::Other,
+                DiffLine::Other,
+                DiffLine::Removed,
+                DiffLine::Added,
+            ]
+        );
+        assert_eq!(
+            DiffLines::default().classify("+++ /dev/null\t2020-01-01"),
+            DiffLine::Header(None)
+        );
+    }
+
+    #[test]
+    fn format_csv_and_tsv() {
+        let rule = |width| "\u{2500}".repeat(width);
+        assert_eq!(
+            format_data(
+                "csv",
+                "name,age,city\nAlice,30,\"Berlin, DE\"\n\"Bob \"\"B\"\"\",4,\n"
+            ),
+            Some(format!(
+                "name    \u{2502} age \u{2502} city
+{}\u{253c}{}\u{253c}{}
+Alice   \u{2502}  30 \u{2502} Berlin, DE
+Bob \"B\" \u{2502}   4 \u{2502}
+",
+                rule(8),
+                rule(5),
+                rule(11)
+            ))
+        );
+        assert_eq!(
+            format_data("tsv", "a\tb\n\"x\t1\n"),
+            Some(format!(
+                "a  \u{2502} b\n{}\u{253c}{}\n\"x \u{2502} 1\n",
+                rule(3),
+                rule(2)
+            ))
+        );
+        assert_eq!(format_data("csv", "a,b\nc\n"), None);
+        assert_eq!(format_data("csv", "a,\"b\n"), None);
+        assert_eq!(format_data("csv", ""), None);
+    }
+
+    #[test]
+    fn reindent_json_data() {
+        assert_eq!(
+            format_data(
+                "json",
+                "{\"b\": [1, 2,{}], \"a\":{\"s\":\"x, \\\"{y}\\\"\"}, \"e\": [ ]}"
+            ),
+            Some(
+                "{
+  \"b\": [
+    1,
+    2,
+    {}
+  ],
+  \"a\": {
+    \"s\": \"x, \\\"{y}\\\"\"
+  },
+  \"e\": []
+}
+"
+                .to_string()
+            )
+        );
+        assert_eq!(format_data("json", "{\"a\": 1,}"), None);
+    }
+}
@@ -9,15 +9,23 @@ use ansi_term::{Colour, Style};
 use pulldown_cmark::Event::*;
 use pulldown_cmark::Tag::*;
 use pulldown_cmark::{CodeBlockKind, CowStr, Event, LinkType, Tag};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
 use std::io;
 use std::io::Write;
 use std::path::Path;
 use syntect::easy::HighlightLines;
-use syntect::highlighting::Theme;
+use syntect::highlighting::{Highlighter, Style as HighlightStyle, Theme};
+use syntect::parsing::{Scope, SyntaxReference, SyntaxSet};
+use unicode_width::UnicodeWidthChar;
+use url::Url;
 
+use crate::code::{self, CodeBlockInfo, CodeOverflow, DiffLine, DiffLines};
+use crate::html::{decode_entities, HtmlToken, HtmlTokenizer};
+use crate::links::{shorten_destination, LinkPlacement};
+use crate::resources::read_all_concurrently;
 use crate::terminal::*;
+use crate::toc::{find_heading, Heading};
 
 /// The "level" the current event occurs at.
 #[derive(Debug, PartialEq)]
@@ -83,15 +91,198 @@ struct LinkContext<'a> {
     current_link_type: Option<LinkType>,
     /// Whether we are inside an inline link currently.
     inside_inline_link: bool,
+    /// The heading the current link points to, if it's an internal link.
+    internal_target: Option<Heading>,
+}
+
+/// Context for code blocks with diffs.
+struct DiffContext<'a> {
+    /// Classifies the lines of the diff.
+    lines: DiffLines,
+    /// The kind of the current line of the diff.
+    ///
+    /// Never has the path of a header.
+    line: DiffLine<'static>,
+    /// The syntax of the code in the diff from the info string, if any.
+    language: Option<&'a SyntaxReference>,
+    /// The highlighter for the old code of the current file, if any.
+    old: Option<HighlightLines<'a>>,
+    /// The highlighter for the new code of the current file, if any.
+    new: Option<HighlightLines<'a>>,
+    /// The style for all headers, i.e. the style of hunk headers in the theme.
+    header_style: HighlightStyle,
+}
+
+impl<'a> DiffContext<'a> {
+    /// Highlight the code in a `line` of the diff, if we know its syntax.
+    ///
+    /// `regions` are the regions of `line` as highlighted by the diff syntax.
+    /// For added, removed and context lines keep the highlighting of the
+    /// marker at the start of the line, and highlight the rest with the
+    /// syntax of the code.  Put headers in the header style, because the diff
+    /// syntax only highlights hunk headers.  Otherwise return `regions` as they
+    /// are.
+    fn highlight<'l>(
+        &mut self,
+        line: &'l str,
+        regions: Vec<(HighlightStyle, &'l str)>,
+        line_start: bool,
+        syntax_set: &SyntaxSet,
+    ) -> Vec<(HighlightStyle, &'l str)> {
+        let (marker, code) = if line_start && line.starts_with(|c| "+- ".contains(c)) {
+            line.split_at(1)
+        } else {
+            ("", line)
+        };
+        let highlighter = match self.line {
+            DiffLine::Added => self.new.as_mut(),
+            DiffLine::Removed => self.old.as_mut(),
+            DiffLine::Context => {
+                // Context lines belong to the old and the new code.
+                if let Some(old) = self.old.as_mut() {
+                    old.highlight(code, syntax_set);
+                }
+                self.new.as_mut()
+            }
+            DiffLine::Header(_) => {
+                let style = self.header_style;
+                return regions.into_iter().map(|(_, text)| (style, text)).collect();
+            }
+            _ => None,
+        };
+        match (highlighter, regions.first()) {
+            (Some(highlighter), Some(&(marker_style, _))) => Some((marker_style, marker))
+                .filter(|(_, marker)| !marker.is_empty())
+                .into_iter()
+                .chain(highlighter.highlight(code, syntax_set))
+                .collect(),
+            _ => regions,
+        }
+    }
+}
+
+/// Context for code blocks.
+struct CodeContext<'a> {
+    /// Whether we are inside a code block.
+    inside: bool,
+    /// The number of code blocks so far, including the current one.
+    block_number: usize,
+    /// The number of the next line, if we number lines of the current code
+    /// block.
+    line_number: Option<u64>,
+    /// Whether we are at the start of a line of code.
+    line_start: bool,
+    /// The column in the current line of code, after the gutter.
+    column: usize,
+    /// Whether we truncated the current line of code.
+    truncated: bool,
+    /// Guessed syntaxes of code blocks without language, by number.
+    guessed_syntaxes: HashMap<usize, &'a SyntaxReference>,
+    /// Context for the current code block if it has a diff.
+    diff: Option<DiffContext<'a>>,
+}
+
+/// The width of the mark at the start of a wrapped line of code.
+const CODE_CONTINUATION_WIDTH: usize = 2;
+
+/// The width of the left and right border of framed code blocks.
+const CODE_FRAME_WIDTH: usize = 4;
+
+/// The width of the gutter with line numbers.
+const LINE_NUMBER_WIDTH: usize = 7;
+
+/// Images read and rendered ahead of time.
+///
+/// Maps every prefetched URL to the rendered image.  Leaves out images we
+/// failed to read or render, to try these again when writing the image.
+#[derive(Default)]
+struct PrefetchedImages {
+    /// Images rendered for iTerm2.
+    iterm2: HashMap<Url, Vec<u8>>,
+    /// Images rendered for Kitty.
+    kitty: HashMap<Url, KittyImage>,
+}
+
+/// Keep only the successful `reads` of URLs.
+fn successful_reads<T>(reads: HashMap<Url, Option<T>>) -> HashMap<Url, T> {
+    reads
+        .into_iter()
+        .filter_map(|(url, read)| read.map(|value| (url, value)))
+        .collect()
+}
+
+/// What to do at the end tag of an HTML element.
+enum HtmlEnd<'a> {
+    /// Nothing.
+    Nothing,
+    /// End the equivalent markdown tag.
+    Tag(Tag<'a>),
+    /// Drop the style of the element.
+    Style,
+    /// Write a suffix in the style of the element, and drop the style.
+    Suffix(&'static str),
+    /// End a block.
+    Block,
+    /// Drop the style of the element and end its block.
+    StyledBlock,
+}
+
+/// Where we are in the current line of a block of HTML.
+///
+/// HTML collapses whitespace, and we drop whitespace at the start and the end
+/// of lines.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum HtmlLine {
+    /// We wrote nothing in this line yet.
+    Start,
+    /// We just wrote a line break for `<br>`.
+    Break,
+    /// We wrote text.
+    Text,
+    /// We wrote text, and need to write a space before more text.
+    Space,
+}
+
+/// An open HTML element.
+struct OpenHtmlElement<'a> {
+    /// The name of the element.
+    name: String,
+    /// What to do at the end tag.
+    end: HtmlEnd<'a>,
+}
+
+/// Context for HTML.
+struct HtmlContext<'a> {
+    /// Splits HTML into tokens.
+    tokenizer: HtmlTokenizer,
+    /// Whether we are in a block of HTML.
+    block: bool,
+    /// Where we are in the current line of a block of HTML.
+    line: HtmlLine,
+    /// HTML elements we interpreted and did not close yet, innermost last.
+    open: Vec<OpenHtmlElement<'a>>,
+    /// The markdown blocks we are in, innermost last.
+    blocks: Vec<BlockMark>,
+}
+
+/// The state of HTML and styles at the start of a markdown block.
+///
+/// HTML elements opened inside a markdown block end with the block.
+struct BlockMark {
+    /// How many HTML elements were open.
+    open: usize,
+    /// How many styles were on the style stack.
+    style_depth: usize,
 }
 
 /// Context for images.
-#[derive(Debug)]
 struct ImageContext {
     /// Whether we currently write an inline image.
     ///
     /// Suppresses all text output.
     inline_image: bool,
+    /// Images we already read and rendered.
+    prefetched: PrefetchedImages,
 }
 
 /// Context for TTY rendering.
@@ -112,6 +303,8 @@ pub struct Context<'a, 'b, W: Write> {
     /// Otherwise we are either outside of a code block or in a code block we
     /// cannot highlight.
     current_highlighter: Option<HighlightLines<'a>>,
+    /// Context for code blocks.
+    code: CodeContext<'a>,
     /// Context for styling
     style: StyleContext,
     /// Context for the current block.
@@ -120,10 +313,17 @@ pub struct Context<'a, 'b, W: Write> {
     links: LinkContext<'b>,
     /// Context for images.
     image: ImageContext,
+    /// Context for HTML.
+    html: HtmlContext<'b>,
     /// The kind of the current list item.
     ///
     /// A stack of kinds to address nested lists.
     list_item_kind: Vec<ListItemKind>,
+    /// All headings of the document, to number headings and resolve internal
+    /// links.
+    headings: Vec<Heading>,
+    /// The index of the next heading we write in `headings`.
+    next_heading: usize,
 }
 
 impl<'a, 'b, W: Write> Context<'a, 'b, W> {
@@ -139,6 +339,16 @@ impl<'a, 'b, W: Write> Context<'a, 'b, W> {
             writer,
             theme,
             current_highlighter: None,
+            code: CodeContext {
+                inside: false,
+                block_number: 0,
+                line_number: None,
+                line_start: false,
+                column: 0,
+                truncated: false,
+                guessed_syntaxes: HashMap::new(),
+                diff: None,
+            },
             style: StyleContext {
                 current: Style::new(),
                 previous: Vec::new(),
@@ -154,26 +364,206 @@ impl<'a, 'b, W: Write> Context<'a, 'b, W> {
                 next_link_index: 1,
                 current_link_type: None,
                 inside_inline_link: false,
+                internal_target: None,
             },
             image: ImageContext {
                 inline_image: false,
+                prefetched: PrefetchedImages::default(),
+            },
+            html: HtmlContext {
+                tokenizer: HtmlTokenizer::default(),
+                block: false,
+                line: HtmlLine::Start,
+                open: Vec::new(),
+                blocks: Vec::new(),
             },
             list_item_kind: Vec::new(),
+            headings: Vec::new(),
+            next_heading: 0,
         }
     }
 
+    /// Set the `headings` of the document, to number headings and resolve
+    /// internal links.
+    ///
+    /// `next` is the index of the first heading we write, if we only write a
+    /// section of the document.
+    pub fn set_headings(&mut self, headings: Vec<Heading>, next: usize) {
+        self.headings = headings;
+        self.next_heading = next;
+    }
+
     /// Resolve a reference in the input.
     ///
-    /// If `reference` parses as URL return the parsed URL.  Otherwise assume
-    /// `reference` is a file path, resolve it against `base_dir` and turn it
-    /// into a file:// URL.  If this also fails return `None`.
-    fn resolve_reference(&self, reference: &str) -> Option<url::Url> {
-        use url::Url;
+    /// If `reference` parses as URL return the parsed URL.  Otherwise, if the
+    /// settings have a base URL, resolve `reference` against the base URL.
+    /// Otherwise assume `reference` is a file path, resolve it against
+    /// `base_dir` and turn it into a file:// URL.  If this also fails return
+    /// `None`.
+    ///
+    /// Without a base URL return `None` for references to anchors, because
+    /// `base_dir` is not the document itself.  Local documents get their
+    /// `file://` URL as base URL, so only documents without a URL, e.g. from
+    /// standard input, resolve against `base_dir`.
+    fn resolve_reference(&self, reference: &str) -> Option<Url> {
         Url::parse(reference)
-            .or_else(|_| Url::from_file_path(self.base_dir.join(reference)))
+            .or_else(|_| match self.settings.base_url {
+                Some(ref base_url) => base_url.join(reference).map_err(|_| ()),
+                None if reference.starts_with('#') => Err(()),
+                None => Url::from_file_path(self.base_dir.join(reference)),
+            })
             .ok()
     }
 
+    /// Resolve the `destination` of a link of the given `link_type`.
+    ///
+    /// Turn email autolinks into `mailto:` URLs; resolve all other links like
+    /// any other reference, which leaves absolute URLs with any scheme, e.g.
+    /// `tel:` or `ssh:`, as they are.
+    fn resolve_link(&self, link_type: LinkType, destination: &str) -> Option<Url> {
+        match link_type {
+            LinkType::Email => Url::parse(&format!("mailto:{}", destination)).ok(),
+            _ => self.resolve_reference(destination),
+        }
+    }
+
+    /// Guess the syntax of all code blocks without language in `events`.
+    ///
+    /// Remember the syntax by the number of the code block, to highlight the
+    /// code block with this syntax.
+    pub fn guess_languages(&mut self, events: &[Event<'b>]) {
+        for (index, block) in code::code_blocks(events).iter().enumerate() {
+            if CodeBlockInfo::parse(&block.info).language.is_none() {
+                if let Some(syntax) = code::guess_syntax(&self.settings.syntax_set, &block.contents)
+                {
+                    self.code.guessed_syntaxes.insert(index + 1, syntax);
+                }
+            }
+        }
+    }
+
+    /// Read and render all images in `events` ahead of time.
+    ///
+    /// Read all images the terminal can show inline and resource access
+    /// permits concurrently, to avoid a network round trip for each image
+    /// while writing the document.
+    pub fn prefetch_images(&mut self, events: &[Event<'b>]) {
+        let mut urls = Vec::new();
+        let mut tokenizer = HtmlTokenizer::default();
+        for event in events {
+            match event {
+                Start(Image(_, link, _)) => urls.extend(self.resolve_reference(link)),
+                Html(html) if self.settings.interpret_html => {
+                    for token in tokenizer.tokenize(html) {
+                        match token {
+                            HtmlToken::StartTag { ref name, .. } if name == "img" => urls.extend(
+                                token
+                                    .attribute("src")
+                                    .and_then(|source| self.resolve_reference(source)),
+                            ),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        urls.retain(|url| self.settings.resource_access.permits(url));
+        match self.settings.terminal_capabilities.image {
+            ImageCapability::ITerm2(ref iterm2) => {
+                let iterm2 = iterm2.clone();
+                let http = self.settings.http.clone();
+                self.image.prefetched.iterm2 =
+                    successful_reads(read_all_concurrently(urls, move |url| {
+                        iterm2.read_and_render(url, &http).ok()
+                    }));
+            }
+            ImageCapability::Kitty(ref kitty) => {
+                let kitty = kitty.clone();
+                let http = self.settings.http.clone();
+                self.image.prefetched.kitty =
+                    successful_reads(read_all_concurrently(urls, move |url| {
+                        kitty.read_and_render(url, &http).ok()
+                    }));
+            }
+            // Terminology reads images on its own
+            ImageCapability::Terminology(_) | ImageCapability::None => {}
+        }
+    }
+
+    /// Write the image at `url` inline, at the given `size`.
+    ///
+    /// Return whether the terminal could show the image.
+    fn write_inline_image(&mut self, url: &Url, size: ImageSize) -> Result<bool, Box<dyn Error>> {
+        match self.settings.terminal_capabilities.image {
+            ImageCapability::Terminology(ref terminology) => {
+                terminology.write_inline_image(
+                    &mut self.writer,
+                    self.settings.terminal_size,
+                    url,
+                    size,
+                )?;
+                Ok(true)
+            }
+            ImageCapability::ITerm2(ref iterm2) => {
+                let contents = match self.image.prefetched.iterm2.get(url) {
+                    Some(prefetched) => Some(prefetched.clone()),
+                    None => iterm2.read_and_render(url, &self.settings.http).ok(),
+                };
+                match contents {
+                    Some(contents) => {
+                        iterm2.write_inline_image(self.writer, url.as_str(), &contents, size)?;
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                }
+            }
+            ImageCapability::Kitty(ref kitty) => {
+                let kitty_image = match self.image.prefetched.kitty.get(url) {
+                    Some(prefetched) => Some(prefetched.clone()),
+                    None => kitty.read_and_render(url, &self.settings.http).ok(),
+                };
+                match kitty_image.and_then(|image| image.scaled(size).ok()) {
+                    Some(kitty_image) => {
+                        kitty.write_inline_image(self.writer, kitty_image)?;
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                }
+            }
+            ImageCapability::None => Ok(false),
+        }
+    }
+
+    /// Write the HTML image `token`.
+    ///
+    /// Write the image inline if the terminal can show it, otherwise write its
+    /// alternative text and its source.
+    fn write_html_image(&mut self, token: &HtmlToken) -> Result<(), Box<dyn Error>> {
+        let source = token.attribute("src");
+        let url = source
+            .and_then(|source| self.resolve_reference(source))
+            .filter(|url| self.settings.resource_access.permits(url));
+        let size = ImageSize {
+            width: html_pixels(token.attribute("width")),
+            height: html_pixels(token.attribute("height")),
+        };
+        let inline = match url {
+            Some(ref url) => self.write_inline_image(url, size)?,
+            None => false,
+        };
+        if !inline {
+            let alt = token.attribute("alt").unwrap_or("");
+            self.write_styled_current(alt)?;
+            if let Some(source) = source {
+                let separator = if alt.is_empty() { "" } else { " " };
+                let style = self.style.current.fg(Colour::Blue);
+                self.write_styled(&style, format!("{}({})", separator, source))?;
+            }
+        }
+        Ok(())
+    }
+
     /// Start a new block.
     ///
     /// Set `block_context` accordingly, and separate this block from the
@@ -238,6 +628,14 @@ impl<'a, 'b, W: Write> Context<'a, 'b, W> {
         };
     }
 
+    /// Drop all styles pushed after the style stack had the given `depth`.
+    fn drop_styles_to(&mut self, depth: usize) {
+        if depth < self.style.previous.len() {
+            self.style.current = self.style.previous[depth];
+            self.style.previous.truncate(depth);
+        }
+    }
+
     /// Write `text` with the given `style`.
     fn write_styled<S: AsRef<str>>(&mut self, style: &Style, text: S) -> io::Result<()> {
         match self.settings.terminal_capabilities.style {
@@ -268,8 +666,17 @@ impl<'a, 'b, W: Write> Context<'a, 'b, W> {
 
     /// Add a link to the context.
     ///
-    /// Return the index of the link.
+    /// Return the index of the link.  Reuse the index of a pending link with
+    /// the same destination.
     fn add_link(&mut self, destination: CowStr<'b>, title: CowStr<'b>) -> usize {
+        if let Some(link) = self
+            .links
+            .pending_links
+            .iter()
+            .find(|link| link.destination == destination)
+        {
+            return link.index;
+        }
         let index = self.links.next_link_index;
         self.links.next_link_index += 1;
         self.links.pending_links.push_back(Link {
@@ -280,6 +687,16 @@ impl<'a, 'b, W: Write> Context<'a, 'b, W> {
         index
     }
 
+    /// Get the `destination` of a link for display.
+    ///
+    /// Shorten `destination` to the link width of the settings, if any.
+    fn display_destination(&self, destination: &str) -> String {
+        match self.settings.link_width {
+            Some(width) => shorten_destination(destination, width),
+            None => destination.to_string(),
+        }
+    }
+
     /// Write all pending links.
     ///
     /// Empty all pending links afterwards.
@@ -288,7 +705,12 @@ impl<'a, 'b, W: Write> Context<'a, 'b, W> {
             self.newline()?;
             let link_style = self.style.current.fg(Colour::Blue);
             while let Some(link) = self.links.pending_links.pop_front() {
-                let link_text = format!("[{}]: {} {}", link.index, link.destination, link.title);
+                let link_text = format!(
+                    "[{}]: {} {}",
+                    link.index,
+                    self.display_destination(&link.destination),
+                    link.title
+                );
                 self.write_styled(&link_style, link_text)?;
                 self.newline()?
             }
@@ -303,19 +725,361 @@ impl<'a, 'b, W: Write> Context<'a, 'b, W> {
         self.newline()
     }
 
+    /// Write the top border of a code block in `language`.
+    ///
+    /// Show the number of the code block in the border if code blocks have
+    /// numbers.  In framed code blocks also show the language.
+    fn write_code_top_border(&mut self, language: Option<&str>) -> io::Result<()> {
+        let number = if self.settings.number_code_blocks {
+            Some(format!("#{}", self.code.block_number))
+        } else {
+            None
+        };
+        let style = self.style.current.fg(Colour::Green);
+        if self.settings.code_frame {
+            let width = self.code_frame_width();
+            let label = number
+                .into_iter()
+                .chain(language.map(ToString::to_string))
+                .collect::<Vec<_>>();
+            let label = if label.is_empty() {
+                String::new()
+            } else {
+                format!("\u{2500} {} ", label.join(" "))
+            };
+            let rule = "\u{2500}".repeat(width.saturating_sub(label.chars().count() + 2));
+            self.write_styled(&style, format!("\u{250c}{}{}\u{2510}", label, rule))?;
+            self.newline()
+        } else {
+            match number {
+                Some(number) => {
+                    let separator = "\u{2500}".repeat(self.settings.terminal_size.width.min(20));
+                    self.write_styled(&style, format!("{} {}", separator, number))?;
+                    self.newline()
+                }
+                None => self.write_border(),
+            }
+        }
+    }
+
+    /// Write the bottom border of a code block.
+    fn write_code_bottom_border(&mut self) -> io::Result<()> {
+        if self.settings.code_frame {
+            if !self.code.line_start {
+                // The code did not end with a newline
+                self.end_code_line()?;
+            }
+            let rule = "\u{2500}".repeat(self.code_frame_width().saturating_sub(2));
+            self.indent()?;
+            let style = self.style.current.fg(Colour::Green);
+            self.write_styled(&style, format!("\u{2514}{}\u{2518}", rule))?;
+            self.newline()
+        } else {
+            self.write_border()
+        }
+    }
+
     /// Write highlighted `text`.
     ///
     /// If the code context has a highlighter, use it to highlight `text` and
     /// write it.  Otherwise write `text` without highlighting.
     fn write_highlighted(&mut self, text: CowStr<'b>) -> io::Result<()> {
+        let line_by_line = self.code.inside
+            && (self.code.line_number.is_some()
+                || self.settings.code_frame
+                || self.settings.code_overflow != CodeOverflow::None
+                || self.code.diff.is_some());
+        if line_by_line {
+            // Text may span many lines of code, or end in the middle of a line
+            for line in text.split_inclusive('\n') {
+                if self.code.line_start {
+                    self.start_diff_line(line);
+                    self.start_code_line()?;
+                }
+                match self.settings.code_overflow {
+                    CodeOverflow::None => self.write_code(line)?,
+                    CodeOverflow::Wrap | CodeOverflow::Truncate => {
+                        self.write_overflowing_code(line)?
+                    }
+                }
+                self.code.line_start = line.ends_with('\n');
+                if self.code.line_start {
+                    self.end_code_line()?;
+                }
+            }
+            Ok(())
+        } else {
+            self.write_highlighted_text(&text)
+        }
+    }
+
+    /// The width of the frame of a code block.
+    ///
+    /// Without frames code blocks take the whole width, because we only indent
+    /// framed code blocks.
+    fn code_frame_width(&self) -> usize {
+        let indent = if self.settings.code_frame {
+            self.block.indent_level
+        } else {
+            0
+        };
+        self.settings.terminal_size.width.saturating_sub(indent)
+    }
+
+    /// The number of columns for a line of code.
+    ///
+    /// Subtract the indentation, the frame and the gutter from the width of the
+    /// terminal.
+    fn code_width(&self) -> usize {
+        let gutter = match self.code.line_number {
+            Some(_) => LINE_NUMBER_WIDTH,
+            None => 0,
+        };
+        let frame = if self.settings.code_frame {
+            CODE_FRAME_WIDTH
+        } else {
+            0
+        };
+        self.code_frame_width()
+            .saturating_sub(frame + gutter)
+            .max(CODE_CONTINUATION_WIDTH + 2)
+    }
+
+    /// The background of code, if any.
+    ///
+    /// Put added lines of diffs on green, and removed lines on red; put other
+    /// code on the code background of framed code blocks.
+    fn code_background(&self) -> Option<Colour> {
+        match self.code.diff.as_ref().map(|diff| diff.line) {
+            Some(DiffLine::Added) => Some(Colour::Fixed(22)),
+            Some(DiffLine::Removed) => Some(Colour::Fixed(52)),
+            _ => self
+                .settings
+                .code_background
+                .filter(|_| self.settings.code_frame),
+        }
+    }
+
+    /// The style for code, on the code background, if any.
+    fn code_style(&self, style: Style) -> Style {
+        match self.code_background() {
+            Some(background) => style.on(background),
+            None => style,
+        }
+    }
+
+    /// Start a `line` of a diff, if the current code block has a diff.
+    ///
+    /// Remember the kind of line, for its background.  If a header names a
+    /// new file highlight the code of this file, unless the info string of
+    /// the code block gives a language.
+    fn start_diff_line(&mut self, line: &str) {
+        let syntax_set = &self.settings.syntax_set;
+        let theme = self.theme;
+        if let Some(ref mut diff) = self.code.diff {
+            diff.line = match diff.lines.classify(line) {
+                DiffLine::Header(Some(path)) => {
+                    if diff.language.is_none() {
+                        let syntax = code::find_syntax_for_path(syntax_set, path);
+                        diff.old = syntax.map(|syntax| HighlightLines::new(syntax, theme));
+                        diff.new = syntax.map(|syntax| HighlightLines::new(syntax, theme));
+                    }
+                    DiffLine::Header(None)
+                }
+                DiffLine::Header(None) => DiffLine::Header(None),
+                DiffLine::Added => DiffLine::Added,
+                DiffLine::Removed => DiffLine::Removed,
+                DiffLine::Context => DiffLine::Context,
+                DiffLine::Other => DiffLine::Other,
+            };
+        }
+    }
+
+    /// Write the left border of a framed code block, after indenting.
+    ///
+    /// Do nothing if code blocks have no frame.
+    fn write_left_code_frame(&mut self) -> io::Result<()> {
+        if self.settings.code_frame {
+            self.indent()?;
+            let style = self.style.current.fg(Colour::Green);
+            self.write_styled(&style, "\u{2502}")?;
+            self.write_styled(&self.code_style(Style::new()), " ")?;
+        }
+        Ok(())
+    }
+
+    /// Start a line of code.
+    ///
+    /// Write the left border of framed code blocks, and the line number if
+    /// any.
+    fn start_code_line(&mut self) -> io::Result<()> {
+        self.write_left_code_frame()?;
+        self.write_line_number()?;
+        self.code.column = 0;
+        self.code.truncated = false;
+        Ok(())
+    }
+
+    /// End a line of code.
+    ///
+    /// In framed code blocks fill the line up to the right border, and write
+    /// the right border.
+    fn end_code_line(&mut self) -> io::Result<()> {
+        if self.settings.code_frame {
+            let padding = self.code_width().saturating_sub(self.code.column) + 1;
+            self.write_styled(&self.code_style(Style::new()), " ".repeat(padding))?;
+            let style = self.style.current.fg(Colour::Green);
+            self.write_styled(&style, "\u{2502}")?;
+        }
+        self.newline()
+    }
+
+    /// Write a `line` of code, and wrap or truncate it at the code width.
+    ///
+    /// `line` may be a part of a line only.
+    fn write_overflowing_code(&mut self, line: &str) -> io::Result<()> {
+        let regions = self.highlight_code(line);
+        let width = self.code_width();
+        // The end of the last character of the line, if `line` ends the line
+        let line_end = line.strip_suffix('\n').map(str::len);
+        let mut offset = 0;
+        for (style, text) in regions {
+            // The start of the text we did not write yet
+            let mut start = 0;
+            for (index, c) in text.char_indices() {
+                let char_width = c.width().unwrap_or(0);
+                let overflows = self.code.column + char_width > width;
+                match self.settings.code_overflow {
+                    CodeOverflow::Wrap
+                        if overflows && self.code.column > CODE_CONTINUATION_WIDTH =>
+                    {
+                        self.write_code_fragment(style, &text[start..index])?;
+                        start = index;
+                        self.write_code_continuation()?;
+                    }
+                    CodeOverflow::Truncate if !self.code.truncated => {
+                        // Keep the last column for the ellipsis, unless the
+                        // last character of the line fits
+                        let is_last = line_end == Some(offset + index + c.len_utf8());
+                        if overflows || (self.code.column + char_width == width && !is_last) {
+                            self.write_code_fragment(style, &text[start..index])?;
+                            let style = self.code_style(Style::new().dimmed());
+                            self.write_styled(&style, "\u{2026}")?;
+                            self.code.column += 1;
+                            self.code.truncated = true;
+                        }
+                    }
+                    _ => {}
+                }
+                if self.code.truncated {
+                    start = index + c.len_utf8();
+                } else {
+                    self.code.column += char_width;
+                }
+            }
+            self.write_code_fragment(style, &text[start..])?;
+            offset += text.len();
+        }
+        Ok(())
+    }
+
+    /// Write a `line` of code as it is.
+    fn write_code(&mut self, line: &str) -> io::Result<()> {
+        for (style, text) in self.highlight_code(line) {
+            self.write_code_fragment(style, text)?;
+            self.code.column += text.chars().filter_map(|c| c.width()).sum::<usize>();
+        }
+        Ok(())
+    }
+
+    /// Highlight a `line` of code with the current highlighter.
+    ///
+    /// Return the regions of `line` with their highlighting style, or all of
+    /// `line` without a style if we do not highlight.  Leave out the newline at
+    /// the end of `line`.
+    fn highlight_code<'l>(&mut self, line: &'l str) -> Vec<(Option<HighlightStyle>, &'l str)> {
+        let line_start = self.code.column == 0;
+        let regions = match (
+            &mut self.current_highlighter,
+            &self.settings.terminal_capabilities.style,
+        ) {
+            (Some(ref mut highlighter), StyleCapability::Ansi(_)) => {
+                let regions = highlighter.highlight(line, &self.settings.syntax_set);
+                match self.code.diff {
+                    Some(ref mut diff) => {
+                        diff.highlight(line, regions, line_start, &self.settings.syntax_set)
+                    }
+                    None => regions,
+                }
+                .into_iter()
+                .map(|(style, text)| (Some(style), text))
+                .collect()
+            }
+            _ => vec![(None, line)],
+        };
+        regions
+            .into_iter()
+            .map(|(style, text)| (style, text.trim_end_matches('\n')))
+            .filter(|(_, text)| !text.is_empty())
+            .collect()
+    }
+
+    /// Write a `fragment` of code in the given highlighting `style`.
+    ///
+    /// Without a highlighting style write `fragment` in the current style.
+    fn write_code_fragment(
+        &mut self,
+        style: Option<HighlightStyle>,
+        fragment: &str,
+    ) -> io::Result<()> {
+        if fragment.is_empty() {
+            return Ok(());
+        }
+        let background = self.code_background();
+        match (style, &self.settings.terminal_capabilities.style) {
+            (Some(style), StyleCapability::Ansi(ref ansi)) => {
+                highlighting::write_as_ansi_on(self.writer, ansi, &[(style, fragment)], background)
+            }
+            _ => self.write_styled(&self.code_style(self.style.current), fragment),
+        }
+    }
+
+    /// Continue a wrapped line of code in the next line.
+    ///
+    /// Leave the gutter empty, and mark the continuation.
+    fn write_code_continuation(&mut self) -> io::Result<()> {
+        self.end_code_line()?;
+        self.write_left_code_frame()?;
+        let gutter = match self.code.line_number {
+            Some(_) => "     \u{2502} ",
+            None => "",
+        };
+        let style = self.code_style(Style::new().dimmed());
+        self.write_styled(&style, format!("{}\u{21aa} ", gutter))?;
+        self.code.column = CODE_CONTINUATION_WIDTH;
+        Ok(())
+    }
+
+    /// Write the number of the next line of code in a dimmed gutter.
+    fn write_line_number(&mut self) -> io::Result<()> {
+        if let Some(number) = self.code.line_number {
+            let style = self.code_style(Style::new().dimmed());
+            self.write_styled(&style, format!("{:>4} \u{2502} ", number))?;
+            self.code.line_number = Some(number + 1);
+        }
+        Ok(())
+    }
+
+    /// Write `text`, highlighted with the current highlighter if any.
+    fn write_highlighted_text(&mut self, text: &str) -> io::Result<()> {
         if let (Some(ref mut highlighter), StyleCapability::Ansi(ref ansi)) = (
             &mut self.current_highlighter,
             &self.settings.terminal_capabilities.style,
         ) {
-            let regions = highlighter.highlight(&text, &self.settings.syntax_set);
+            let regions = highlighter.highlight(text, &self.settings.syntax_set);
             highlighting::write_as_ansi(self.writer, ansi, &regions)?;
         } else {
-            self.write_styled_current(&text)?;
+            self.write_styled_current(text)?;
         }
         Ok(())
     }
@@ -328,6 +1092,133 @@ impl<'a, 'b, W: Write> Context<'a, 'b, W> {
             MarkCapability::None => Ok(()),
         }
     }
+
+    /// Start inline text in a block of HTML.
+    ///
+    /// Start inline text, and write a pending space.
+    fn start_inline_html(&mut self) -> io::Result<()> {
+        self.start_inline_text()?;
+        if let HtmlLine::Space = self.html.line {
+            write!(self.writer, " ")?;
+        }
+        self.html.line = HtmlLine::Text;
+        Ok(())
+    }
+
+    /// Write `text` between HTML tags.
+    ///
+    /// In blocks of HTML collapse whitespace like a browser.
+    fn write_html_text(&mut self, text: &str) -> io::Result<()> {
+        let text = decode_entities(text);
+        if self.html.block {
+            let words = text.split_whitespace().collect::<Vec<_>>();
+            if text.starts_with(char::is_whitespace) && self.html.line == HtmlLine::Text {
+                self.html.line = HtmlLine::Space;
+            }
+            if !words.is_empty() {
+                self.start_inline_html()?;
+                self.write_styled_current(words.join(" "))?;
+                if text.ends_with(char::is_whitespace) {
+                    self.html.line = HtmlLine::Space;
+                }
+            }
+            Ok(())
+        } else {
+            self.write_styled_current(text)
+        }
+    }
+
+    /// Write HTML we do not interpret as it is.
+    fn write_raw_html(&mut self, html: &str) -> io::Result<()> {
+        if self.html.block {
+            self.start_inline_html()?;
+        }
+        self.write_styled(&self.style.current.fg(Colour::Green), html)
+    }
+
+    /// End the current line of a block of HTML.
+    ///
+    /// End inline text if we wrote text in this line, or after a line break.
+    fn end_html_line(&mut self) -> io::Result<()> {
+        match self.html.line {
+            HtmlLine::Text | HtmlLine::Space => self.end_inline_text_with_margin()?,
+            // We already wrote the line break
+            HtmlLine::Break => self.block.level = BlockLevel::Block,
+            HtmlLine::Start => {}
+        }
+        self.html.line = HtmlLine::Start;
+        Ok(())
+    }
+
+    /// Write a horizontal rule across the terminal.
+    fn write_rule(&mut self) -> io::Result<()> {
+        self.start_inline_text()?;
+        let rule = "\u{2550}".repeat(self.settings.terminal_size.width);
+        let style = self.style.current.fg(Colour::Green);
+        self.write_styled(&style, rule)?;
+        self.end_inline_text_with_margin()
+    }
+
+    /// End the current block of HTML, if any.
+    pub fn end_html_block(&mut self) -> io::Result<()> {
+        if self.html.block {
+            self.html.block = false;
+            self.end_html_line()?;
+        }
+        Ok(())
+    }
+
+    /// Format the section `number` of a heading.
+    ///
+    /// Return `None` if headings have no numbers.
+    fn heading_number(&self, number: &[u32]) -> Option<String> {
+        self.settings
+            .heading_numbering
+            .as_ref()
+            .and_then(|numbering| numbering.format(number))
+    }
+
+    /// Write a table of contents for the given `headings`.
+    ///
+    /// Write an outline of `headings`, indented by level.  If the terminal
+    /// supports inline links and we know the URL of the document link every
+    /// entry to the anchor of its heading.
+    ///
+    /// Set a mark before the table of contents, to jump back to the table of
+    /// contents from the marks of the headings.
+    pub fn write_table_of_contents(&mut self, headings: &[Heading]) -> io::Result<()> {
+        let min_level = match headings.iter().map(|heading| heading.level).min() {
+            Some(level) => level,
+            None => return Ok(()),
+        };
+        self.start_inline_text()?;
+        self.set_mark_if_supported()?;
+        let style = self.style.current.fg(Colour::Blue);
+        for (i, heading) in headings.iter().enumerate() {
+            if 0 < i {
+                self.newline_and_indent()?;
+            }
+            let indent = 2 * (heading.level - min_level) as usize;
+            write!(self.writer, "{}\u{2022} ", " ".repeat(indent))?;
+            if let Some(number) = self.heading_number(&heading.number) {
+                self.write_styled(&style, number + " ")?;
+            }
+            let url = self
+                .settings
+                .base_url
+                .as_ref()
+                .and_then(|url| url.join(&format!("#{}", heading.anchor)).ok());
+            match (&self.settings.terminal_capabilities.links, url) {
+                (LinkCapability::OSC8(ref osc8), Some(url)) => {
+                    osc8.set_link_url(self.writer, url)?;
+                    self.write_styled(&style, &heading.text)?;
+                    osc8.clear_link(self.writer)?;
+                }
+                _ => self.write_styled(&style, &heading.text)?,
+            }
+        }
+        self.end_inline_text_with_margin()
+    }
 }
 
 /// Write a single `event` in the given context.
@@ -335,17 +1226,18 @@ pub fn write_event<'a, 'b, W: Write>(
     mut ctx: Context<'a, 'b, W>,
     event: Event<'b>,
 ) -> Result<Context<'a, 'b, W>, Box<dyn Error>> {
+    match event {
+        Html(_) => {}
+        // Any other event ends a block of HTML
+        _ => ctx.end_html_block()?,
+    }
     match event {
         SoftBreak | HardBreak => {
             ctx.newline_and_indent()?;
             Ok(ctx)
         }
         Rule => {
-            ctx.start_inline_text()?;
-            let rule = "\u{2550}".repeat(ctx.settings.terminal_size.width as usize);
-            let style = ctx.style.current.fg(Colour::Green);
-            ctx.write_styled(&style, rule)?;
-            ctx.end_inline_text_with_margin()?;
+            ctx.write_rule()?;
             Ok(ctx)
         }
         Code(code) => {
@@ -367,8 +1259,28 @@ pub fn write_event<'a, 'b, W: Write>(
             ctx.write_highlighted(CowStr::Borrowed(marker))?;
             Ok(ctx)
         }
-        Start(tag) => start_tag(ctx, tag),
+        Start(tag) => {
+            if is_block_tag(&tag) {
+                ctx.html.blocks.push(BlockMark {
+                    open: ctx.html.open.len(),
+                    style_depth: ctx.style.previous.len(),
+                });
+            }
+            start_tag(ctx, tag)
+        }
+        End(tag) if is_block_tag(&tag) => match ctx.html.blocks.pop() {
+            Some(mark) => {
+                // Close HTML elements left open inside the block, lest their
+                // styles leak into the rest of the document
+                ctx = close_html_elements(ctx, mark.open)?;
+                ctx = end_tag(ctx, tag)?;
+                ctx.drop_styles_to(mark.style_depth);
+                Ok(ctx)
+            }
+            None => end_tag(ctx, tag),
+        },
         End(tag) => end_tag(ctx, tag),
+        Html(content) if ctx.settings.interpret_html => write_html(ctx, &content),
         Html(content) => {
             ctx.write_styled(&ctx.style.current.fg(Colour::Green), content)?;
             Ok(ctx)
@@ -377,6 +1289,265 @@ pub fn write_event<'a, 'b, W: Write>(
     }
 }
 
+/// Whether `tag` is a markdown block, as opposed to inline markup.
+fn is_block_tag(tag: &Tag) -> bool {
+    !matches!(
+        tag,
+        Emphasis | Strong | Strikethrough | Link(..) | Image(..)
+    )
+}
+
+/// Whether `name` is the name of a void HTML element, which has no end tag.
+fn is_void_html_element(name: &str) -> bool {
+    matches!(
+        name,
+        "br" | "hr" | "img" | "wbr" | "input" | "meta" | "link" | "source"
+    )
+}
+
+/// Parse an HTML `width` or `height` in pixels, like `100` or `100px`.
+///
+/// Return `None` for other units, like percentages.
+fn html_pixels(value: Option<&str>) -> Option<u32> {
+    let value = value?.trim();
+    value.strip_suffix("px").unwrap_or(value).parse().ok()
+}
+
+/// Write the start of the HTML element `name` with `attributes`.
+///
+/// Return what to do at the end tag, or `None` if we do not interpret this
+/// element.
+fn start_html_element<'a, 'b, W: Write>(
+    mut ctx: Context<'a, 'b, W>,
+    name: &str,
+    token: &HtmlToken,
+) -> Result<(Context<'a, 'b, W>, Option<HtmlEnd<'b>>), Box<dyn Error>> {
+    let end = match name {
+        "b" | "strong" => Some(HtmlEnd::Tag(Strong)),
+        "i" | "em" | "cite" | "var" | "dfn" => Some(HtmlEnd::Tag(Emphasis)),
+        "s" | "del" | "strike" => Some(HtmlEnd::Tag(Strikethrough)),
+        "a" => Some(match token.attribute("href") {
+            Some(href) => HtmlEnd::Tag(Link(
+                LinkType::Inline,
+                href.to_string().into(),
+                token.attribute("title").unwrap_or("").to_string().into(),
+            )),
+            None => HtmlEnd::Nothing,
+        }),
+        "span" | "font" | "small" | "big" | "abbr" | "picture" => Some(HtmlEnd::Nothing),
+        "u" | "ins" => {
+            ctx.set_style(ctx.style.current.underline());
+            Some(HtmlEnd::Style)
+        }
+        "code" | "tt" | "samp" => {
+            ctx.set_style(ctx.style.current.fg(Colour::Yellow));
+            Some(HtmlEnd::Style)
+        }
+        "kbd" | "mark" => {
+            ctx.set_style(ctx.style.current.reverse());
+            Some(HtmlEnd::Style)
+        }
+        "sup" | "sub" => {
+            ctx.start_inline_html()?;
+            ctx.write_styled_current(if name == "sup" { "^(" } else { "_(" })?;
+            ctx.set_style(ctx.style.current);
+            Some(HtmlEnd::Suffix(")"))
+        }
+        "br" => {
+            if let BlockLevel::Inline = ctx.block.level {
+                ctx.newline_and_indent()?;
+                ctx.html.line = HtmlLine::Break;
+            }
+            None
+        }
+        "hr" => {
+            ctx.end_html_line()?;
+            ctx.write_rule()?;
+            None
+        }
+        "img" => {
+            ctx.start_inline_html()?;
+            ctx.write_html_image(token)?;
+            None
+        }
+        "p" | "div" | "center" | "details" | "section" | "article" | "header" | "footer"
+        | "figure" | "figcaption" => {
+            ctx.end_html_line()?;
+            Some(HtmlEnd::Block)
+        }
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            ctx.end_html_line()?;
+            ctx.set_style(Style::new().fg(Colour::Blue).bold());
+            Some(HtmlEnd::StyledBlock)
+        }
+        "summary" => {
+            ctx.end_html_line()?;
+            ctx.start_inline_text()?;
+            ctx.set_style(ctx.style.current.bold());
+            ctx.write_styled_current("\u{25b6} ")?;
+            ctx.html.line = HtmlLine::Text;
+            Some(HtmlEnd::StyledBlock)
+        }
+        _ => return Ok((ctx, None)),
+    };
+    match end {
+        Some(HtmlEnd::Tag(ref tag)) => {
+            ctx = start_tag(ctx, tag.clone())?;
+            Ok((ctx, end))
+        }
+        // Void elements have no end, and we do not need to keep them open
+        _ if is_void_html_element(name) => Ok((ctx, None)),
+        _ => Ok((ctx, end)),
+    }
+}
+
+/// Write the end of an HTML element.
+fn end_html_element<'a, 'b, W: Write>(
+    mut ctx: Context<'a, 'b, W>,
+    end: HtmlEnd<'b>,
+) -> Result<Context<'a, 'b, W>, Box<dyn Error>> {
+    match end {
+        HtmlEnd::Nothing => {}
+        HtmlEnd::Tag(tag) => ctx = end_tag(ctx, tag)?,
+        HtmlEnd::Style => ctx.drop_style(),
+        HtmlEnd::Suffix(suffix) => {
+            ctx.write_styled_current(suffix)?;
+            ctx.drop_style();
+        }
+        HtmlEnd::Block => {
+            ctx.end_html_line()?;
+        }
+        HtmlEnd::StyledBlock => {
+            ctx.drop_style();
+            ctx.end_html_line()?;
+        }
+    }
+    Ok(ctx)
+}
+
+/// Close all open HTML elements but the first `count` ones, innermost first.
+fn close_html_elements<'a, 'b, W: Write>(
+    mut ctx: Context<'a, 'b, W>,
+    count: usize,
+) -> Result<Context<'a, 'b, W>, Box<dyn Error>> {
+    if count < ctx.html.open.len() {
+        for element in ctx.html.open.split_off(count).into_iter().rev() {
+            ctx = end_html_element(ctx, element.end)?;
+        }
+    }
+    Ok(ctx)
+}
+
+/// The HTML elements we interpret.
+const KNOWN_HTML_ELEMENTS: &[&str] = &[
+    "a",
+    "abbr",
+    "article",
+    "b",
+    "big",
+    "br",
+    "center",
+    "cite",
+    "code",
+    "del",
+    "details",
+    "dfn",
+    "div",
+    "em",
+    "figcaption",
+    "figure",
+    "font",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "i",
+    "img",
+    "ins",
+    "kbd",
+    "mark",
+    "p",
+    "picture",
+    "s",
+    "samp",
+    "section",
+    "small",
+    "span",
+    "strike",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "tt",
+    "u",
+    "var",
+];
+
+/// Whether we interpret the HTML element `name`.
+fn is_known_html_element(name: &str) -> bool {
+    KNOWN_HTML_ELEMENTS.contains(&name)
+}
+
+/// Interpret `html` in the given context.
+///
+/// Map common tags to styles, line breaks and blocks, hide comments, and write
+/// all other HTML as it is.  If `html` starts a block of HTML, treat all HTML
+/// up to the next event which is not HTML as a single block.
+fn write_html<'a, 'b, W: Write>(
+    mut ctx: Context<'a, 'b, W>,
+    html: &str,
+) -> Result<Context<'a, 'b, W>, Box<dyn Error>> {
+    // Inline HTML never ends with a newline; blocks of HTML come line by line
+    let starts_block = match ctx.block.level {
+        BlockLevel::Block => true,
+        BlockLevel::Inline => html.ends_with('\n'),
+    };
+    if !ctx.html.block && starts_block {
+        ctx.html.block = true;
+        ctx.html.line = HtmlLine::Start;
+    }
+    for token in ctx.html.tokenizer.tokenize(html) {
+        match token {
+            HtmlToken::Comment => {}
+            HtmlToken::Text(text) => ctx.write_html_text(text)?,
+            HtmlToken::Other(raw) => ctx.write_raw_html(raw)?,
+            HtmlToken::StartTag {
+                ref name,
+                self_closing,
+                raw,
+                ..
+            } => {
+                if !is_known_html_element(name) {
+                    ctx.write_raw_html(raw)?;
+                } else if !self_closing || is_void_html_element(name) {
+                    let (new_ctx, end) = start_html_element(ctx, name, &token)?;
+                    ctx = new_ctx;
+                    if let Some(end) = end {
+                        ctx.html.open.push(OpenHtmlElement {
+                            name: name.clone(),
+                            end,
+                        });
+                    }
+                }
+            }
+            HtmlToken::EndTag { ref name, raw } => {
+                if !is_known_html_element(name) {
+                    ctx.write_raw_html(raw)?;
+                } else if let Some(index) = ctx.html.open.iter().rposition(|e| &e.name == name) {
+                    // Close the element and all elements left open inside it
+                    ctx = close_html_elements(ctx, index)?;
+                }
+            }
+        }
+    }
+    Ok(ctx)
+}
+
 /// Write the start of a `tag` in the given context.
 fn start_tag<'a, 'b, W: Write>(
     mut ctx: Context<'a, 'b, W>,
@@ -387,11 +1558,22 @@ fn start_tag<'a, 'b, W: Write>(
         Heading(level) => {
             // Before we start a new header, write all pending links to keep
             // them close to the text where they appeared in
-            ctx.write_pending_links()?;
+            match ctx.settings.link_placement {
+                LinkPlacement::Section | LinkPlacement::Paragraph => ctx.write_pending_links()?,
+                _ => {}
+            }
             ctx.start_inline_text()?;
             ctx.set_mark_if_supported()?;
             ctx.set_style(Style::new().fg(Colour::Blue).bold());
-            ctx.write_styled_current("\u{2504}".repeat(level as usize))?
+            ctx.write_styled_current("\u{2504}".repeat(level as usize))?;
+            let number = ctx
+                .headings
+                .get(ctx.next_heading)
+                .and_then(|heading| ctx.heading_number(&heading.number));
+            ctx.next_heading += 1;
+            if let Some(number) = number {
+                ctx.write_styled_current(number + " ")?;
+            }
         }
         BlockQuote => {
             ctx.block.indent_level += 4;
@@ -402,17 +1584,47 @@ fn start_tag<'a, 'b, W: Write>(
         }
         CodeBlock(kind) => {
             ctx.start_inline_text()?;
-            ctx.write_border()?;
+            let info = match kind {
+                CodeBlockKind::Indented => CodeBlockInfo::default(),
+                CodeBlockKind::Fenced(ref info) => CodeBlockInfo::parse(info),
+            };
+            ctx.code.block_number += 1;
+            ctx.write_code_top_border(info.language)?;
+            ctx.code.inside = true;
+            ctx.code.line_number = info.first_line_number(ctx.settings.line_numbers);
+            ctx.code.line_start = true;
             // Try to get a highlighter for the current code.
-            ctx.current_highlighter = match kind {
-                CodeBlockKind::Indented => None,
-                CodeBlockKind::Fenced(name) if name.is_empty() => None,
-                CodeBlockKind::Fenced(name) => ctx
-                    .settings
-                    .syntax_set
-                    .find_syntax_by_token(&name)
-                    .map(|syntax| HighlightLines::new(syntax, ctx.theme)),
+            let syntax = match info.language {
+                Some(language) => code::find_syntax(
+                    &ctx.settings.syntax_set,
+                    &ctx.settings.syntax_aliases,
+                    language,
+                ),
+                None => ctx
+                    .code
+                    .guessed_syntaxes
+                    .get(&ctx.code.block_number)
+                    .copied(),
             };
+            ctx.current_highlighter = syntax.map(|syntax| HighlightLines::new(syntax, ctx.theme));
+            ctx.code.diff = syntax.filter(|syntax| syntax.name == "Diff").map(|_| {
+                let language = info.inner_language.and_then(|language| {
+                    code::find_syntax(
+                        &ctx.settings.syntax_set,
+                        &ctx.settings.syntax_aliases,
+                        language,
+                    )
+                });
+                DiffContext {
+                    lines: DiffLines::default(),
+                    line: DiffLine::Other,
+                    language,
+                    old: language.map(|syntax| HighlightLines::new(syntax, ctx.theme)),
+                    new: language.map(|syntax| HighlightLines::new(syntax, ctx.theme)),
+                    header_style: Highlighter::new(ctx.theme)
+                        .style_for_stack(&[Scope::new("meta.diff.range").unwrap()]),
+                }
+            });
             if ctx.current_highlighter.is_none() {
                 // If we found no highlighter (code block had no language or
                 // a language synctex doesn't support) we set a style to
@@ -456,13 +1668,12 @@ fn start_tag<'a, 'b, W: Write>(
         Strong => ctx.set_style(ctx.style.current.bold()),
         Link(link_type, destination, _) => {
             ctx.links.current_link_type = Some(link_type);
+            ctx.links.internal_target = find_heading(&ctx.headings, &destination).cloned();
             // Do nothing if the terminal doesn’t support inline links of if `destination` is no
             // valid URL:  We will write a reference link when closing the link tag.
             match ctx.settings.terminal_capabilities.links {
                 LinkCapability::OSC8(ref osc8) => {
-                    // TODO: check link type (first tuple element) to write proper mailto link for
-                    // emails
-                    if let Some(url) = ctx.resolve_reference(&destination) {
+                    if let Some(url) = ctx.resolve_link(link_type, &destination) {
                         osc8.set_link_url(ctx.writer, url)?;
                         ctx.links.inside_inline_link = true;
                     }
@@ -474,28 +1685,8 @@ fn start_tag<'a, 'b, W: Write>(
             let url = ctx
                 .resolve_reference(&link)
                 .filter(|url| ctx.settings.resource_access.permits(url));
-            match (&ctx.settings.terminal_capabilities.image, url) {
-                (ImageCapability::Terminology(ref terminology), Some(ref url)) => {
-                    terminology.write_inline_image(
-                        &mut ctx.writer,
-                        ctx.settings.terminal_size,
-                        url,
-                    )?; /*  */
-                    ctx.image.inline_image = true;
-                }
-                (ImageCapability::ITerm2(ref iterm2), Some(ref url)) => {
-                    if let Ok(contents) = iterm2.read_and_render(url) {
-                        iterm2.write_inline_image(ctx.writer, url.as_str(), &contents)?;
-                        ctx.image.inline_image = true;
-                    }
-                }
-                (ImageCapability::Kitty(ref kitty), Some(ref url)) => {
-                    if let Ok(kitty_image) = kitty.read_and_render(url) {
-                        kitty.write_inline_image(ctx.writer, kitty_image)?;
-                        ctx.image.inline_image = true;
-                    }
-                }
-                (_, None) | (ImageCapability::None, _) => {}
+            if let Some(url) = url {
+                ctx.image.inline_image = ctx.write_inline_image(&url, ImageSize::default())?;
             }
         }
     };
@@ -508,7 +1699,12 @@ fn end_tag<'a, 'b, W: Write>(
     tag: Tag<'b>,
 ) -> Result<Context<'a, 'b, W>, Box<dyn Error>> {
     match tag {
-        Paragraph => ctx.end_inline_text_with_margin()?,
+        Paragraph => {
+            ctx.end_inline_text_with_margin()?;
+            if let LinkPlacement::Paragraph = ctx.settings.link_placement {
+                ctx.write_pending_links()?;
+            }
+        }
         Heading(_) => {
             ctx.drop_style();
             ctx.end_inline_text_with_margin()?
@@ -521,6 +1717,9 @@ fn end_tag<'a, 'b, W: Write>(
             ctx.end_inline_text_with_margin()?
         }
         CodeBlock(_) => {
+            ctx.code.inside = false;
+            ctx.code.diff = None;
+            ctx.code.line_number = None;
             match ctx.current_highlighter {
                 None => ctx.drop_style(),
                 Some(_) => {
@@ -530,7 +1729,7 @@ fn end_tag<'a, 'b, W: Write>(
                     ctx.current_highlighter = None;
                 }
             }
-            ctx.write_border()?;
+            ctx.write_code_bottom_border()?;
             // Move back to block context, but do not add a dedicated margin
             // because the bottom border we printed above already acts as
             // margin.
@@ -558,6 +1757,7 @@ fn end_tag<'a, 'b, W: Write>(
         }
         Strong => ctx.drop_style(),
         Link(_, destination, title) => {
+            let internal_target = ctx.links.internal_target.take();
             if ctx.links.inside_inline_link {
                 match ctx.settings.terminal_capabilities.links {
                     LinkCapability::OSC8(ref osc8) => {
@@ -566,7 +1766,7 @@ fn end_tag<'a, 'b, W: Write>(
                     LinkCapability::None => {}
                 }
                 ctx.links.inside_inline_link = false;
-            } else {
+            } else if internal_target.is_none() {
                 // When we did not write an inline link, create a normal reference
                 // link instead.  Even if the terminal supports inline links this
                 // can still happen for anything that's not a valid URL.
@@ -575,14 +1775,32 @@ fn end_tag<'a, 'b, W: Write>(
                         // Do nothing for autolinks: We shouldn't repeat the link destination,
                         // if the link text _is_ the destination.
                     }
-                    _ => {
-                        // Reference link
-                        let index = ctx.add_link(destination, title);
-                        let style = ctx.style.current.fg(Colour::Blue);
-                        ctx.write_styled(&style, format!("[{}]", index))?
-                    }
+                    _ => match ctx.settings.link_placement {
+                        LinkPlacement::Inline => {
+                            let style = ctx.style.current.fg(Colour::Blue);
+                            let text = format!(" ({})", ctx.display_destination(&destination));
+                            ctx.write_styled(&style, text)?
+                        }
+                        LinkPlacement::None => {}
+                        _ => {
+                            // Reference link
+                            let index = ctx.add_link(destination, title);
+                            let style = ctx.style.current.fg(Colour::Blue);
+                            ctx.write_styled(&style, format!("[{}]", index))?
+                        }
+                    },
                 }
             }
+            if let Some(heading) = internal_target {
+                // Point to the heading of an internal link instead of a reference
+                // link, because the anchor alone tells little about the target.
+                let number = ctx
+                    .heading_number(&heading.number)
+                    .map(|number| number + " ")
+                    .unwrap_or_default();
+                let style = ctx.style.current.fg(Colour::Blue);
+                ctx.write_styled(&style, format!(" (\u{2192} {}{})", number, heading.text))?
+            }
         }
         Image(_, link, _) => {
             if !ctx.image.inline_image {
@@ -596,3 +1814,109 @@ fn end_tag<'a, 'b, W: Write>(
     };
     Ok(ctx)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use syntect::highlighting::ThemeSet;
+
+    fn settings(base_url: Option<&str>) -> Settings {
+        Settings {
+            base_url: base_url.map(|url| Url::parse(url).unwrap()),
+            ..Settings::default()
+        }
+    }
+
+    fn resolve(settings: &Settings, reference: &str) -> Option<String> {
+        let theme = &ThemeSet::load_defaults().themes["Solarized (dark)"];
+        let mut sink = Vec::new();
+        let context = Context::new(&mut sink, settings, Path::new("/foo/bar"), theme);
+        context
+            .resolve_reference(reference)
+            .map(|url| url.to_string())
+    }
+
+    fn resolve_link(settings: &Settings, link_type: LinkType, destination: &str) -> Option<String> {
+        let theme = &ThemeSet::load_defaults().themes["Solarized (dark)"];
+        let mut sink = Vec::new();
+        let context = Context::new(&mut sink, settings, Path::new("/foo/bar"), theme);
+        context
+            .resolve_link(link_type, destination)
+            .map(|url| url.to_string())
+    }
+
+    #[test]
+    fn resolve_email_autolink_to_mailto() {
+        let settings = settings(None);
+        assert_eq!(
+            resolve_link(&settings, LinkType::Email, "user@example.com"),
+            Some("mailto:user@example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn resolve_links_with_other_schemes() {
+        let settings = settings(Some("https://example.com/README.md"));
+        for destination in &[
+            "mailto:user@example.com",
+            "tel:+49-123-456",
+            "ssh://git@example.com/repo.git",
+            "vscode://file/home/user/README.md",
+        ] {
+            assert_eq!(
+                resolve_link(&settings, LinkType::Inline, destination),
+                Some(destination.to_string())
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn resolve_reference_against_base_dir() {
+        let settings = settings(None);
+        assert_eq!(
+            resolve(&settings, "images/logo.png"),
+            Some("file:///foo/bar/images/logo.png".to_string())
+        );
+        assert_eq!(
+            resolve(&settings, "https://example.com/logo.png"),
+            Some("https://example.com/logo.png".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn resolve_reference_in_local_document() {
+        let settings = settings(Some("file:///foo/bar/README.md"));
+        assert_eq!(
+            resolve(&settings, "images/logo.png"),
+            Some("file:///foo/bar/images/logo.png".to_string())
+        );
+        assert_eq!(
+            resolve(&settings, "../LICENSE"),
+            Some("file:///foo/LICENSE".to_string())
+        );
+        assert_eq!(
+            resolve(&settings, "#usage"),
+            Some("file:///foo/bar/README.md#usage".to_string())
+        );
+    }
+
+    #[test]
+    fn resolve_reference_against_base_url() {
+        let settings = settings(Some("https://example.com/owner/repo/README.md"));
+        assert_eq!(
+            resolve(&settings, "images/logo.png"),
+            Some("https://example.com/owner/repo/images/logo.png".to_string())
+        );
+        assert_eq!(
+            resolve(&settings, "/logo.png"),
+            Some("https://example.com/logo.png".to_string())
+        );
+        assert_eq!(
+            resolve(&settings, "file:///foo/logo.png"),
+            Some("file:///foo/logo.png".to_string())
+        );
+    }
+}
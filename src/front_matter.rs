@@ -0,0 +1,284 @@
+// Copyright 2020 Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Front matter of documents.
+//!
+//! Static site generators like Hugo or Jekyll and note apps like Obsidian put
+//! metadata in YAML or TOML at the very beginning of a document.
+
+use crate::terminal::StyleCapability;
+use crate::Settings;
+use ansi_term::{Colour, Style};
+use std::io::{Result, Write};
+
+/// The format of front matter.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FrontMatterFormat {
+    /// YAML front matter, between lines of `---`.
+    Yaml,
+    /// TOML front matter, between lines of `+++`.
+    Toml,
+}
+
+impl FrontMatterFormat {
+    /// The delimiter of front matter in this format.
+    fn delimiter(self) -> &'static str {
+        match self {
+            FrontMatterFormat::Yaml => "---",
+            FrontMatterFormat::Toml => "+++",
+        }
+    }
+}
+
+/// Front matter of a document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrontMatter {
+    /// The format of the front matter.
+    pub format: FrontMatterFormat,
+    /// The front matter as written in the document, without delimiters.
+    pub source: String,
+    /// The top-level entries of the front matter, in order.
+    ///
+    /// Each value is formatted for display: Lists are joined with `, `, and
+    /// nested tables written inline, like `{key: value}`.
+    pub entries: Vec<(String, String)>,
+}
+
+/// Format a YAML value for display.
+fn format_yaml(value: &serde_yaml::Value) -> String {
+    use serde_yaml::Value;
+    match value {
+        Value::Null => String::new(),
+        Value::Bool(value) => value.to_string(),
+        Value::Number(value) => value.to_string(),
+        Value::String(value) => value.clone(),
+        Value::Sequence(values) => values
+            .iter()
+            .map(format_yaml)
+            .collect::<Vec<_>>()
+            .join(", "),
+        Value::Mapping(mapping) => format!(
+            "{{{}}}",
+            mapping
+                .iter()
+                .map(|(key, value)| format!("{}: {}", format_yaml(key), format_yaml(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Format a TOML value for display.
+fn format_toml(value: &toml::Value) -> String {
+    use toml::Value;
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Integer(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Boolean(value) => value.to_string(),
+        Value::Datetime(value) => value.to_string(),
+        Value::Array(values) => values
+            .iter()
+            .map(format_toml)
+            .collect::<Vec<_>>()
+            .join(", "),
+        Value::Table(table) => format!(
+            "{{{}}}",
+            table
+                .iter()
+                .map(|(key, value)| format!("{}: {}", key, format_toml(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Parse the entries of front matter `source` in `format`.
+///
+/// Return `None` if `source` is no table of keys and values.
+fn parse_entries(format: FrontMatterFormat, source: &str) -> Option<Vec<(String, String)>> {
+    match format {
+        FrontMatterFormat::Yaml => match serde_yaml::from_str(source).ok()? {
+            serde_yaml::Value::Mapping(mapping) => Some(
+                mapping
+                    .iter()
+                    .map(|(key, value)| (format_yaml(key), format_yaml(value)))
+                    .collect(),
+            ),
+            // Empty front matter
+            serde_yaml::Value::Null => Some(Vec::new()),
+            _ => None,
+        },
+        FrontMatterFormat::Toml => source.parse::<toml::Value>().ok().and_then(|value| {
+            value.as_table().map(|table| {
+                table
+                    .iter()
+                    .map(|(key, value)| (key.clone(), format_toml(value)))
+                    .collect()
+            })
+        }),
+    }
+}
+
+/// Split front matter off `input`.
+///
+/// If `input` starts with a line of `---` or `+++` and has a matching closing
+/// line, and the lines between are a table of keys and values in YAML or TOML
+/// respectively, return the front matter and the byte offset of the document
+/// after the front matter.
+///
+/// Otherwise return `None`; `input` then starts with a thematic break or a
+/// setext heading rather than front matter.
+pub fn split_front_matter(input: &str) -> Option<(FrontMatter, usize)> {
+    let format = [FrontMatterFormat::Yaml, FrontMatterFormat::Toml]
+        .iter()
+        .copied()
+        .find(|format| input.lines().next().map(str::trim_end) == Some(format.delimiter()))?;
+    let start = input.find('\n')? + 1;
+    let mut offset = start;
+    while offset < input.len() {
+        let end = input[offset..]
+            .find('\n')
+            .map_or(input.len(), |index| offset + index + 1);
+        let line = input[offset..end].trim_end();
+        let closes =
+            line == format.delimiter() || (format == FrontMatterFormat::Yaml && line == "...");
+        if closes {
+            let source = &input[start..offset];
+            let entries = parse_entries(format, source)?;
+            return Some((
+                FrontMatter {
+                    format,
+                    source: source.to_string(),
+                    entries,
+                },
+                end,
+            ));
+        }
+        offset = end;
+    }
+    None
+}
+
+/// Write `front_matter` as a block of keys and values to `writer`.
+///
+/// Align all values, and use `settings` to style the keys.  Follow the block
+/// with an empty line to separate it from the document.
+pub fn write_front_matter<W: Write>(
+    writer: &mut W,
+    settings: &Settings,
+    front_matter: &FrontMatter,
+) -> Result<()> {
+    if front_matter.entries.is_empty() {
+        return Ok(());
+    }
+    let width = front_matter
+        .entries
+        .iter()
+        .map(|(key, _)| key.chars().count())
+        .max()
+        .unwrap_or(0);
+    let style = Style::new().fg(Colour::Purple).bold();
+    for (key, value) in &front_matter.entries {
+        let key = format!("{}:", key);
+        match settings.terminal_capabilities.style {
+            StyleCapability::None => write!(writer, "{}", key)?,
+            StyleCapability::Ansi(ref ansi) => ansi.write_styled(writer, &style, &key)?,
+        }
+        let padding = width + 1 - key.chars().count();
+        writeln!(writer, "{} {}", " ".repeat(padding), value)?;
+    }
+    writeln!(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn entries(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn yaml_front_matter() {
+        let input = "---
+title: Hello
+tags: [foo, bar]
+draft: false
+author:
+  name: Jane
+---
+# Hello
+";
+        let (front_matter, offset) = split_front_matter(input).unwrap();
+        assert_eq!(front_matter.format, FrontMatterFormat::Yaml);
+        assert_eq!(
+            front_matter.entries,
+            entries(&[
+                ("title", "Hello"),
+                ("tags", "foo, bar"),
+                ("draft", "false"),
+                ("author", "{name: Jane}"),
+            ])
+        );
+        assert_eq!(&input[offset..], "# Hello\n");
+    }
+
+    #[test]
+    fn toml_front_matter() {
+        let input = "+++
+title = \"Hello\"
+date = 2020-05-01
+tags = [\"foo\", \"bar\"]
++++
+
+Text
+";
+        let (front_matter, offset) = split_front_matter(input).unwrap();
+        assert_eq!(front_matter.format, FrontMatterFormat::Toml);
+        assert_eq!(
+            front_matter.entries,
+            entries(&[
+                ("title", "Hello"),
+                ("date", "2020-05-01"),
+                ("tags", "foo, bar")
+            ])
+        );
+        assert_eq!(&input[offset..], "\nText\n");
+    }
+
+    #[test]
+    fn yaml_front_matter_closed_with_dots() {
+        let (front_matter, _) = split_front_matter("---\ntitle: Hello\n...\n").unwrap();
+        assert_eq!(front_matter.entries, entries(&[("title", "Hello")]));
+    }
+
+    #[test]
+    fn no_front_matter() {
+        assert_eq!(split_front_matter("# Hello\n---\n"), None);
+        // A thematic break followed by a setext heading
+        assert_eq!(split_front_matter("---\nHello\n---\n"), None);
+        // Unclosed
+        assert_eq!(split_front_matter("---\ntitle: Hello\n"), None);
+    }
+
+    #[test]
+    fn write_front_matter_as_block() {
+        let settings = Settings::default();
+        let (front_matter, _) =
+            split_front_matter("---\ntitle: Hello\ntags: [foo, bar]\n---\n").unwrap();
+        let mut sink = Vec::new();
+        write_front_matter(&mut sink, &settings, &front_matter).unwrap();
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "title: Hello\ntags:  foo, bar\n\n"
+        );
+    }
+}
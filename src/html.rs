@@ -0,0 +1,313 @@
+// Copyright 2020 Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Tokenize the HTML in markdown documents.
+//!
+//! This is no HTML parser: It only splits HTML into tags, comments and text,
+//! to interpret the few tags which commonly appear in markdown documents.
+
+/// A token of HTML.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HtmlToken<'a> {
+    /// A start tag, like `<a href="…">` or `<br/>`.
+    StartTag {
+        /// The lowercase tag name.
+        name: String,
+        /// The attributes of the tag, with lowercase names.
+        attributes: Vec<(String, String)>,
+        /// Whether the tag closes itself, like `<br/>`.
+        self_closing: bool,
+        /// The tag as written in the document.
+        raw: &'a str,
+    },
+    /// An end tag, like `</a>`.
+    EndTag {
+        /// The lowercase tag name.
+        name: String,
+        /// The tag as written in the document.
+        raw: &'a str,
+    },
+    /// A comment, or a part of a comment.
+    Comment,
+    /// Text between tags.
+    Text(&'a str),
+    /// Anything else, like `<!DOCTYPE html>`, or broken tags.
+    Other(&'a str),
+}
+
+impl<'a> HtmlToken<'a> {
+    /// Get the value of the attribute `name` of a start tag.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        match self {
+            HtmlToken::StartTag { attributes, .. } => attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// Split HTML into tokens.
+///
+/// Keep track of comments across calls, because the markdown parser passes
+/// every line of a HTML block separately.
+#[derive(Debug, Default)]
+pub struct HtmlTokenizer {
+    /// Whether we are inside a comment.
+    in_comment: bool,
+}
+
+/// Whether `c` may appear in a tag or attribute name.
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == ':' || c == '_'
+}
+
+/// Parse the tag at the start of `html`.
+///
+/// Return the token and its length, or `None` if `html` does not start with a
+/// complete tag.
+fn parse_tag(html: &str) -> Option<(HtmlToken<'_>, usize)> {
+    let (is_end, mut rest) = match html.strip_prefix("</") {
+        Some(rest) => (true, rest),
+        None => (false, &html[1..]),
+    };
+    let name_length = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+    if name_length == 0 || !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let name = rest[..name_length].to_lowercase();
+    rest = &rest[name_length..];
+    let mut attributes = Vec::new();
+    let mut self_closing = false;
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with('>') {
+            rest = &rest[1..];
+            break;
+        } else if rest.starts_with("/>") {
+            self_closing = true;
+            rest = &rest[2..];
+            break;
+        }
+        let attribute_length = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        if attribute_length == 0 {
+            return None;
+        }
+        let attribute = rest[..attribute_length].to_lowercase();
+        rest = rest[attribute_length..].trim_start();
+        let value = if rest.starts_with('=') {
+            rest = rest[1..].trim_start();
+            let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'');
+            match quote {
+                Some(quote) => {
+                    let end = rest[1..].find(quote)? + 1;
+                    let value = &rest[1..end];
+                    rest = &rest[end + 1..];
+                    value
+                }
+                None => {
+                    let end = rest
+                        .find(|c: char| c.is_whitespace() || c == '>')
+                        .unwrap_or(rest.len());
+                    let value = &rest[..end];
+                    rest = &rest[end..];
+                    value
+                }
+            }
+        } else {
+            ""
+        };
+        attributes.push((attribute, decode_entities(value)));
+    }
+    let length = html.len() - rest.len();
+    let raw = &html[..length];
+    let token = if is_end {
+        HtmlToken::EndTag { name, raw }
+    } else {
+        HtmlToken::StartTag {
+            name,
+            attributes,
+            self_closing,
+            raw,
+        }
+    };
+    Some((token, length))
+}
+
+impl HtmlTokenizer {
+    /// Split `html` into tokens.
+    pub fn tokenize<'a>(&mut self, html: &'a str) -> Vec<HtmlToken<'a>> {
+        let mut tokens = Vec::new();
+        let mut rest = html;
+        while !rest.is_empty() {
+            if self.in_comment {
+                match rest.find("-->") {
+                    Some(end) => {
+                        self.in_comment = false;
+                        rest = &rest[end + 3..];
+                    }
+                    None => rest = "",
+                }
+                tokens.push(HtmlToken::Comment);
+            } else if rest.starts_with("<!--") {
+                self.in_comment = true;
+                rest = &rest[4..];
+            } else if rest.starts_with('<') {
+                match parse_tag(rest) {
+                    Some((token, length)) => {
+                        tokens.push(token);
+                        rest = &rest[length..];
+                    }
+                    None => {
+                        let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+                        tokens.push(HtmlToken::Other(&rest[..end]));
+                        rest = &rest[end..];
+                    }
+                }
+            } else {
+                let end = rest.find('<').unwrap_or(rest.len());
+                tokens.push(HtmlToken::Text(&rest[..end]));
+                rest = &rest[end..];
+            }
+        }
+        tokens
+    }
+}
+
+/// Decode the most common HTML entities in `text`.
+///
+/// Decode numeric entities and a few named entities; leave all other entities
+/// as they are.
+pub fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| (&rest[1..end], end + 1));
+        let character = entity.and_then(|(name, _)| match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            "copy" => Some('\u{a9}'),
+            "reg" => Some('\u{ae}'),
+            "mdash" => Some('\u{2014}'),
+            "ndash" => Some('\u{2013}'),
+            "hellip" => Some('\u{2026}'),
+            _ if name.starts_with("#x") || name.starts_with("#X") => {
+                u32::from_str_radix(&name[2..], 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+            }
+            _ if name.starts_with('#') => name[1..].parse().ok().and_then(std::char::from_u32),
+            _ => None,
+        });
+        match (character, entity) {
+            (Some(character), Some((_, length))) => {
+                decoded.push(character);
+                rest = &rest[length..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn start_tag<'a>(name: &str, attributes: &[(&str, &str)], raw: &'a str) -> HtmlToken<'a> {
+        HtmlToken::StartTag {
+            name: name.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            self_closing: false,
+            raw,
+        }
+    }
+
+    #[test]
+    fn tokenize_tags_and_text() {
+        let mut tokenizer = HtmlTokenizer::default();
+        assert_eq!(
+            tokenizer.tokenize("<p align=\"center\">Hello <B>world</B><br/>"),
+            vec![
+                start_tag("p", &[("align", "center")], "<p align=\"center\">"),
+                HtmlToken::Text("Hello "),
+                start_tag("b", &[], "<B>"),
+                HtmlToken::Text("world"),
+                HtmlToken::EndTag {
+                    name: "b".to_string(),
+                    raw: "</B>"
+                },
+                HtmlToken::StartTag {
+                    name: "br".to_string(),
+                    attributes: Vec::new(),
+                    self_closing: true,
+                    raw: "<br/>"
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_attributes() {
+        let mut tokenizer = HtmlTokenizer::default();
+        let tokens = tokenizer.tokenize("<img src='logo.png' width=100 alt=\"A &amp; B\" hidden>");
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].attribute("src"), Some("logo.png"));
+        assert_eq!(tokens[0].attribute("width"), Some("100"));
+        assert_eq!(tokens[0].attribute("alt"), Some("A & B"));
+        assert_eq!(tokens[0].attribute("hidden"), Some(""));
+        assert_eq!(tokens[0].attribute("height"), None);
+    }
+
+    #[test]
+    fn tokenize_comments_across_lines() {
+        let mut tokenizer = HtmlTokenizer::default();
+        assert_eq!(
+            tokenizer.tokenize("a<!-- start\n"),
+            vec![HtmlToken::Text("a"), HtmlToken::Comment]
+        );
+        assert_eq!(
+            tokenizer.tokenize("still comment\n"),
+            vec![HtmlToken::Comment]
+        );
+        assert_eq!(
+            tokenizer.tokenize("end -->b"),
+            vec![HtmlToken::Comment, HtmlToken::Text("b")]
+        );
+    }
+
+    #[test]
+    fn tokenize_other() {
+        let mut tokenizer = HtmlTokenizer::default();
+        assert_eq!(
+            tokenizer.tokenize("<!DOCTYPE html><a"),
+            vec![HtmlToken::Other("<!DOCTYPE html>"), HtmlToken::Other("<a")]
+        );
+    }
+
+    #[test]
+    fn decode_common_entities() {
+        assert_eq!(
+            decode_entities("&lt;a&gt; &amp;&copy;&#65;&#x42; &unknown; & done"),
+            "<a> &\u{a9}AB &unknown; & done"
+        );
+    }
+}
@@ -8,25 +8,47 @@
 
 //! Write markdown to TTYs.
 
+use ansi_term::Colour;
 use pulldown_cmark::Event;
+use std::collections::HashMap;
 use std::error::Error;
 use std::io::Write;
 use std::path::Path;
 use syntect::highlighting::ThemeSet;
 use syntect::parsing::SyntaxSet;
+use url::Url;
 
+mod front_matter;
+mod html;
+mod linkify;
+mod links;
 mod magic;
 mod resources;
 mod svg;
+mod syntaxes;
 mod terminal;
+mod toc;
 
+mod code;
 mod context_write;
 
 use context_write::*;
+use linkify::linkify;
 
 // Expose some select things for use in main
-pub use crate::resources::ResourceAccess;
+pub use crate::code::{code_blocks, tangle, CodeBlock, CodeOverflow};
+pub use crate::front_matter::{
+    split_front_matter, write_front_matter, FrontMatter, FrontMatterFormat,
+};
+pub use crate::linkify::GitHubRepository;
+pub use crate::links::LinkPlacement;
+pub use crate::resources::{read_url, HttpHeader, HttpSettings, ResourceAccess};
+pub use crate::syntaxes::load_syntax_set;
 pub use crate::terminal::*;
+pub use crate::toc::{
+    dangling_anchors, find_heading, find_section, table_of_contents, write_outline, Heading,
+    HeadingNumbering, OutlineFormat, SectionPattern,
+};
 
 /// Dump markdown events to a writer.
 pub fn dump_events<'a, W, I>(writer: &mut W, events: I) -> Result<(), Box<dyn Error>>
@@ -49,16 +71,120 @@ pub struct Settings {
     pub terminal_size: TerminalSize,
     /// Whether remote resource access is permitted.
     pub resource_access: ResourceAccess,
+    /// How to request remote resources over HTTP.
+    pub http: HttpSettings,
+    /// Whether to write a table of contents before the document.
+    pub table_of_contents: bool,
+    /// How to number headings, if at all.
+    pub heading_numbering: Option<HeadingNumbering>,
+    /// Where to write the destinations of links.
+    pub link_placement: LinkPlacement,
+    /// The maximum width of link destinations.
+    ///
+    /// If set shorten longer link destinations to this number of characters.
+    pub link_width: Option<usize>,
+    /// The GitHub repository of the document.
+    ///
+    /// If set link issue references, mentions and commit SHAs like GitHub.
+    pub repository: Option<GitHubRepository>,
+    /// Whether to interpret common HTML tags.
+    ///
+    /// If set map common HTML tags to styles and line breaks, and hide HTML
+    /// comments; otherwise write all HTML as it is.
+    pub interpret_html: bool,
+    /// Whether to number the lines of all code blocks.
+    ///
+    /// Code blocks can also ask for line numbers with `linenos` in their info
+    /// string, or refuse them with `nolinenos`.
+    pub line_numbers: bool,
+    /// What to do with lines of code longer than the terminal is wide.
+    pub code_overflow: CodeOverflow,
+    /// Whether to draw a frame around code blocks.
+    ///
+    /// If set show the language of code blocks in the top of their frame.
+    pub code_frame: bool,
+    /// The background colour of framed code blocks.
+    ///
+    /// If set fill framed code blocks with this colour.
+    pub code_background: Option<Colour>,
+    /// Whether to number code blocks.
+    ///
+    /// If set show the number of each code block in its top border, to copy
+    /// code blocks by number with `copy_code_block`.
+    pub number_code_blocks: bool,
+    /// Aliases for languages of code blocks.
+    ///
+    /// Map languages of code blocks to a language token or syntax name in
+    /// `syntax_set`, in addition to built-in aliases like `console` for shell
+    /// sessions.
+    pub syntax_aliases: HashMap<String, String>,
+    /// Whether to guess the language of code blocks without language.
+    ///
+    /// If set highlight indented code blocks and fenced code blocks without
+    /// language according to their shebang line, their first line, or
+    /// typical lines of some languages.
+    pub guess_language: bool,
+    /// Whether to format the contents of code blocks with data.
+    ///
+    /// If set show `csv` and `tsv` code blocks as aligned tables, and
+    /// re-indent `json` code blocks.
+    pub format_data: bool,
+    /// The URL to resolve relative references against.
+    ///
+    /// If set, resolve relative links and images against this URL, like a web
+    /// browser would, instead of against the base directory, and link anchors
+    /// in the table of contents to it.  The `mdcat` binary sets the URL of the
+    /// document here, i.e. a `file://` URL for local files, so it only uses the
+    /// base directory for standard input.
+    pub base_url: Option<Url>,
     /// Syntax set for syntax highlighting of code blocks.
     pub syntax_set: SyntaxSet,
 }
 
+impl Default for Settings {
+    /// Settings for a terminal without any capabilities, with local resources
+    /// only, and the default syntaxes.
+    fn default() -> Settings {
+        Settings {
+            terminal_capabilities: TerminalCapabilities::none(),
+            terminal_size: TerminalSize::default(),
+            resource_access: ResourceAccess::LocalOnly,
+            http: HttpSettings::default(),
+            table_of_contents: false,
+            heading_numbering: None,
+            link_placement: LinkPlacement::default(),
+            link_width: None,
+            repository: None,
+            interpret_html: false,
+            line_numbers: false,
+            code_overflow: CodeOverflow::default(),
+            code_frame: false,
+            code_background: None,
+            number_code_blocks: false,
+            syntax_aliases: HashMap::new(),
+            guess_language: false,
+            format_data: false,
+            base_url: None,
+            syntax_set: SyntaxSet::load_defaults_newlines(),
+        }
+    }
+}
+
 /// Write markdown to a TTY.
 ///
 /// Iterate over Markdown AST `events`, format each event for TTY output and
 /// write the result to a `writer`, using the given `settings` for rendering and
 /// resource access.  `base_dir` denotes the base directory the `events` were
-/// read from, to resolve relative references in the Markdown document.
+/// read from, to resolve relative references in the Markdown document, unless
+/// `settings` has a base URL.
+///
+/// Before writing anything `push_tty` reads all images the terminal can show
+/// concurrently.  If enabled in `settings` it then writes a table of contents.
+///
+/// `push_tty` always turns bare URLs into links, and GitHub references too if
+/// `settings` has a repository.  It resolves internal links like `#anchor` to
+/// the headings of the document, and writes the target heading after the link
+/// text.
 ///
 /// `push_tty` tries to limit output to the given number of TTY `columns` but
 /// does not guarantee that output stays within the column limit.
@@ -66,16 +192,142 @@ pub fn push_tty<'a, 'e, W, I>(
     settings: &Settings,
     writer: &'a mut W,
     base_dir: &'a Path,
-    mut events: I,
+    events: I,
+) -> Result<(), Box<dyn Error>>
+where
+    I: Iterator<Item = Event<'e>>,
+    W: Write,
+{
+    let events = events.collect::<Vec<_>>();
+    let headings = table_of_contents(&events);
+    push_tty_section(settings, writer, base_dir, events.into_iter(), &headings, 0)
+}
+
+/// Write a section of a markdown document to a TTY.
+///
+/// Like `push_tty`, but `events` only cover a section of the document, which
+/// starts at the event with index `start` in the whole document, e.g. the
+/// start of a range from `find_section`.
+///
+/// Number headings and resolve internal links according to the `headings` of
+/// the whole document, as returned by `table_of_contents`.  The table of
+/// contents only covers the headings of the section.
+pub fn push_tty_section<'a, 'e, W, I>(
+    settings: &Settings,
+    writer: &'a mut W,
+    base_dir: &'a Path,
+    events: I,
+    headings: &[Heading],
+    start: usize,
 ) -> Result<(), Box<dyn Error>>
 where
     I: Iterator<Item = Event<'e>>,
     W: Write,
 {
     let theme = &ThemeSet::load_defaults().themes["Solarized (dark)"];
-    events
-        .try_fold(Context::new(writer, settings, base_dir, theme), write_event)?
-        .write_pending_links()?;
+    let events = linkify(events.collect(), settings.repository.as_ref());
+    let events = if settings.format_data {
+        code::format_data_blocks(events)
+    } else {
+        events
+    };
+    let mut context = Context::new(writer, settings, base_dir, theme);
+    context.prefetch_images(&events);
+    if settings.guess_language {
+        context.guess_languages(&events);
+    }
+    let first = headings
+        .iter()
+        .position(|heading| heading.position >= start)
+        .unwrap_or(headings.len());
+    if settings.table_of_contents {
+        let count = table_of_contents(&events).len();
+        let section = &headings[first..(first + count).min(headings.len())];
+        context.write_table_of_contents(section)?;
+    }
+    context.set_headings(headings.to_vec(), first);
+    let mut context = events.into_iter().try_fold(context, write_event)?;
+    context.end_html_block()?;
+    context.write_pending_links()?;
+    Ok(())
+}
+
+/// Write the contents of all fenced code blocks in `events` to `writer`.
+///
+/// Write the contents as they are, without any formatting.  If `language` is
+/// given only write code blocks in this language, ie, with the same language
+/// token, or a language token for the same syntax in `settings`, like `sh`
+/// and `bash`.
+pub fn extract_code<'a, W, I>(
+    writer: &mut W,
+    settings: &Settings,
+    events: I,
+    language: Option<&str>,
+) -> Result<(), Box<dyn Error>>
+where
+    I: IntoIterator<Item = &'a Event<'a>>,
+    W: Write,
+{
+    let syntax = |token: &str| {
+        code::find_syntax(&settings.syntax_set, &settings.syntax_aliases, token)
+            .map(|syntax| syntax.name.as_str())
+    };
+    for block in code_blocks(events).iter().filter(|block| block.fenced) {
+        let wanted = match (language, code::CodeBlockInfo::parse(&block.info).language) {
+            (None, _) => true,
+            (Some(language), Some(block_language)) => {
+                language == block_language
+                    || (syntax(language).is_some() && syntax(language) == syntax(block_language))
+            }
+            (Some(_), None) => false,
+        };
+        if wanted {
+            writer.write_all(block.contents.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy the code block with the given `number` in `events` to the clipboard.
+///
+/// Count code blocks from 1, like `push_tty` numbers them.  Write the raw
+/// contents of the code block to `writer` with the clipboard capability of the
+/// terminal in `settings`.
+///
+/// Fail if the terminal can't copy to the clipboard, or if there's no code
+/// block with `number`.
+pub fn copy_code_block<'a, W, I>(
+    writer: &mut W,
+    settings: &Settings,
+    events: I,
+    number: usize,
+) -> Result<(), Box<dyn Error>>
+where
+    I: IntoIterator<Item = &'a Event<'a>>,
+    W: Write,
+{
+    let clipboard = match settings.terminal_capabilities.clipboard {
+        ClipboardCapability::OSC52(ref clipboard) => clipboard,
+        ClipboardCapability::None => {
+            return Err(format!(
+                "Terminal {} can't copy to the clipboard",
+                settings.terminal_capabilities.name
+            )
+            .into())
+        }
+    };
+    let blocks = code_blocks(events);
+    let block = number
+        .checked_sub(1)
+        .and_then(|index| blocks.get(index))
+        .ok_or_else(|| {
+            format!(
+                "No code block {}, the document has {} code blocks",
+                number,
+                blocks.len()
+            )
+        })?;
+    clipboard.copy(writer, &block.contents)?;
     Ok(())
 }
 
@@ -98,16 +350,680 @@ mod tests {
         let result = String::from_utf8(
             render_string(
                 "_lorem_ **ipsum** dolor **sit** _amet_",
+                &Settings::default(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(result, "lorem ipsum dolor sit amet\n");
+    }
+
+    #[test]
+    fn table_of_contents_before_document() {
+        let result = String::from_utf8(
+            render_string(
+                "# Title\n\nText\n\n## Usage\n\n### Options\n\n## License\n",
                 &Settings {
-                    resource_access: ResourceAccess::LocalOnly,
-                    syntax_set: SyntaxSet::default(),
-                    terminal_capabilities: TerminalCapabilities::none(),
-                    terminal_size: TerminalSize::default(),
+                    table_of_contents: true,
+                    ..Settings::default()
                 },
             )
             .unwrap(),
         )
         .unwrap();
-        assert_eq!(result, "lorem ipsum dolor sit amet\n");
+        assert_eq!(
+            result,
+            "\u{2022} Title
+  \u{2022} Usage
+    \u{2022} Options
+  \u{2022} License
+
+\u{2504}Title
+
+Text
+
+\u{2504}\u{2504}Usage
+
+\u{2504}\u{2504}\u{2504}Options
+
+\u{2504}\u{2504}License
+"
+        );
+    }
+
+    #[test]
+    fn numbered_headings_and_table_of_contents() {
+        let result = String::from_utf8(
+            render_string(
+                "# Title\n\n## Usage\n\n### Options\n\n## License\n",
+                &Settings {
+                    table_of_contents: true,
+                    heading_numbering: Some(HeadingNumbering {
+                        start_level: 2,
+                        format: "{}.".to_string(),
+                    }),
+                    ..Settings::default()
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            "\u{2022} Title
+  \u{2022} 1. Usage
+    \u{2022} 1.1. Options
+  \u{2022} 2. License
+
+\u{2504}Title
+
+\u{2504}\u{2504}1. Usage
+
+\u{2504}\u{2504}\u{2504}1.1. Options
+
+\u{2504}\u{2504}2. License
+"
+        );
+    }
+
+    #[test]
+    fn internal_links_point_to_headings() {
+        let result = String::from_utf8(
+            render_string(
+                "# Usage\n\nSee [options](#options) and [nothing](#nothing).\n\n## Options\n",
+                &Settings {
+                    heading_numbering: Some(HeadingNumbering::default()),
+                    ..Settings::default()
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            "\u{2504}1 Usage
+
+See options (\u{2192} 1.1 Options) and nothing[1].
+
+[1]: #nothing 
+
+\u{2504}\u{2504}1.1 Options
+"
+        );
+    }
+
+    fn render_links(link_placement: LinkPlacement, link_width: Option<usize>) -> String {
+        let markdown = "# Links
+
+See [one](https://example.com/some/long/path) and [two](https://example.com/other).
+
+Again [one](https://example.com/some/long/path).
+
+## End
+";
+        String::from_utf8(
+            render_string(
+                markdown,
+                &Settings {
+                    link_placement,
+                    link_width,
+                    ..Settings::default()
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn links_before_section_reuse_index() {
+        assert_eq!(
+            render_links(LinkPlacement::Section, None),
+            "\u{2504}Links
+
+See one[1] and two[2].
+
+Again one[1].
+
+[1]: https://example.com/some/long/path 
+[2]: https://example.com/other 
+
+\u{2504}\u{2504}End
+"
+        );
+    }
+
+    #[test]
+    fn links_after_paragraph() {
+        assert_eq!(
+            render_links(LinkPlacement::Paragraph, None),
+            "\u{2504}Links
+
+See one[1] and two[2].
+
+[1]: https://example.com/some/long/path 
+[2]: https://example.com/other 
+
+Again one[3].
+
+[3]: https://example.com/some/long/path 
+
+\u{2504}\u{2504}End
+"
+        );
+    }
+
+    #[test]
+    fn links_inline_shortened() {
+        assert_eq!(
+            render_links(LinkPlacement::Inline, Some(20)),
+            "\u{2504}Links
+
+See one (https://ex\u{2026}long/path) and two (https://ex\u{2026}com/other).
+
+Again one (https://ex\u{2026}long/path).
+
+\u{2504}\u{2504}End
+"
+        );
+    }
+
+    #[test]
+    fn links_at_end() {
+        assert_eq!(
+            render_links(LinkPlacement::End, None),
+            "\u{2504}Links
+
+See one[1] and two[2].
+
+Again one[1].
+
+\u{2504}\u{2504}End
+
+[1]: https://example.com/some/long/path 
+[2]: https://example.com/other 
+"
+        );
+    }
+
+    #[test]
+    fn no_links() {
+        assert_eq!(
+            render_links(LinkPlacement::None, None),
+            "\u{2504}Links
+
+See one and two.
+
+Again one.
+
+\u{2504}\u{2504}End
+"
+        );
+    }
+
+    #[test]
+    fn internal_links_out_of_section() {
+        let markdown = "# Intro\n\nText\n\n# Usage\n\nSee [intro](#intro).\n";
+        let events = Parser::new(markdown).collect::<Vec<_>>();
+        let headings = table_of_contents(&events);
+        let section = find_section(&events, &SectionPattern::Anchor("usage".to_string())).unwrap();
+        let mut sink = Vec::new();
+        push_tty_section(
+            &Settings::default(),
+            &mut sink,
+            Path::new("/"),
+            events[section.clone()].iter().cloned(),
+            &headings,
+            section.start,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "\u{2504}Usage\n\nSee intro (\u{2192} Intro).\n"
+        );
+    }
+
+    #[test]
+    fn number_headings_of_section() {
+        let markdown = "# Intro
+
+## Setup
+
+# Usage
+
+## Options
+
+See [setup](#setup) and [options](#options).
+";
+        let settings = Settings {
+            table_of_contents: true,
+            heading_numbering: Some(HeadingNumbering::default()),
+            ..Settings::default()
+        };
+        let events = Parser::new(markdown).collect::<Vec<_>>();
+        let headings = table_of_contents(&events);
+        let section = find_section(&events, &SectionPattern::Anchor("usage".to_string())).unwrap();
+        let mut sink = Vec::new();
+        push_tty_section(
+            &settings,
+            &mut sink,
+            Path::new("/"),
+            events[section.clone()].iter().cloned(),
+            &headings,
+            section.start,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "\u{2022} 2 Usage
+  \u{2022} 2.1 Options
+
+\u{2504}2 Usage
+
+\u{2504}\u{2504}2.1 Options
+
+See setup (\u{2192} 1.1 Setup) and options (\u{2192} 2.1 Options).
+"
+        );
+    }
+
+    #[test]
+    fn interpret_common_html() {
+        let markdown = "<p align=\"center\">
+  <b>mdcat</b><br>
+  <!-- a
+  comment -->
+  cat for <i>markdown</i> &amp; more
+</p>
+
+Press <kbd>Ctrl</kbd>+<kbd>C</kbd>, x<sup>2</sup>.
+
+<details>
+<summary>More</summary>
+
+Hidden text
+
+</details>
+
+<foo>bar</foo>
+";
+        let result = String::from_utf8(
+            render_string(
+                markdown,
+                &Settings {
+                    interpret_html: true,
+                    ..Settings::default()
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            "mdcat
+cat for markdown & more
+
+Press Ctrl+C, x^(2).
+
+\u{25b6} More
+
+Hidden text
+
+<foo>bar</foo>
+"
+        );
+    }
+
+    #[test]
+    fn close_html_elements_at_the_end_of_blocks() {
+        let settings = Settings {
+            terminal_capabilities: TerminalCapabilities::ansi(),
+            interpret_html: true,
+            ..Settings::default()
+        };
+        let render =
+            |markdown| String::from_utf8(render_string(markdown, &settings).unwrap()).unwrap();
+
+        assert_eq!(
+            render("Some <b>bold\n\nPlain\n"),
+            "Some \u{1b}[1mbold\u{1b}[0m\n\nPlain\n"
+        );
+        assert_eq!(
+            render("# Title <kbd>x\n\nPlain\n"),
+            "\u{1b}[1;34m\u{2504}\u{1b}[0m\u{1b}[1;34mTitle \u{1b}[0m\u{1b}[1;7;34mx\u{1b}[0m\n\nPlain\n"
+        );
+        assert_eq!(
+            render("<div>\nA\n<hr>\nB\n</div>\n"),
+            format!("A\n\n\u{1b}[32m{}\u{1b}[0m\n\nB\n", "\u{2550}".repeat(80))
+        );
+    }
+
+    #[test]
+    fn html_images_without_image_support() {
+        let markdown = "<p align=\"center\">
+  <a href=\"https://example.com\"><img src=\"logo.png\" alt=\"Logo\" width=\"100\"></a>
+</p>
+
+A badge <img src=\"badge.svg\"/>.
+";
+        let result = String::from_utf8(
+            render_string(
+                markdown,
+                &Settings {
+                    interpret_html: true,
+                    ..Settings::default()
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            "Logo (logo.png)[1]
+
+A badge (badge.svg).
+
+[1]: https://example.com 
+"
+        );
+    }
+
+    #[test]
+    fn number_lines_of_code_blocks() {
+        use pulldown_cmark::{CodeBlockKind, CowStr, Tag};
+
+        let settings = Settings::default();
+        let result = String::from_utf8(
+            render_string(
+                "```rust,linenos,start=9\nfn main() {\n}\n```\n\n```\nplain\n```\n",
+                &settings,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let border = "\u{2500}".repeat(20);
+        assert_eq!(
+            result,
+            format!(
+                "{border}
+   9 \u{2502} fn main() {{
+  10 \u{2502} }}
+{border}
+
+{border}
+plain
+{border}
+",
+                border = border
+            )
+        );
+
+        // Text may span many lines, or end in the middle of a line
+        let kind = CodeBlockKind::Fenced(CowStr::Borrowed("linenos"));
+        let events = vec![
+            Event::Start(Tag::CodeBlock(kind.clone())),
+            Event::Text(CowStr::Borrowed("a\nb")),
+            Event::Text(CowStr::Borrowed("c\nd\n")),
+            Event::End(Tag::CodeBlock(kind)),
+        ];
+        let mut sink = Vec::new();
+        push_tty(&settings, &mut sink, Path::new("/"), events.into_iter()).unwrap();
+        let result = String::from_utf8(sink).unwrap();
+        assert_eq!(
+            result.lines().skip(1).take(3).collect::<Vec<_>>(),
+            vec!["   1 \u{2502} a", "   2 \u{2502} bc", "   3 \u{2502} d"]
+        );
+    }
+
+    fn render_code_overflow(code_overflow: CodeOverflow) -> String {
+        let markdown = "```\n0123456789abcdefghijABCDEFGHIJ\nshort\n```\n";
+        let settings = Settings {
+            terminal_size: TerminalSize {
+                width: 12,
+                ..TerminalSize::default()
+            },
+            code_overflow,
+            ..Settings::default()
+        };
+        let result = String::from_utf8(render_string(markdown, &settings).unwrap()).unwrap();
+        // Skip the borders
+        let lines = result.lines().collect::<Vec<_>>();
+        lines[1..lines.len() - 1].join("\n")
+    }
+
+    #[test]
+    fn wrap_long_lines_of_code() {
+        assert_eq!(
+            render_code_overflow(CodeOverflow::Wrap),
+            "0123456789ab
+\u{21aa} cdefghijAB
+\u{21aa} CDEFGHIJ
+short"
+        );
+    }
+
+    #[test]
+    fn truncate_long_lines_of_code() {
+        assert_eq!(
+            render_code_overflow(CodeOverflow::Truncate),
+            "0123456789a\u{2026}
+short"
+        );
+    }
+
+    #[test]
+    fn leave_long_lines_of_code() {
+        assert_eq!(
+            render_code_overflow(CodeOverflow::None),
+            "0123456789abcdefghijABCDEFGHIJ
+short"
+        );
+    }
+
+    #[test]
+    fn wrap_long_lines_of_nested_code() {
+        let markdown = "* Item\n\n  ```\n  0123456789abcdefghij\n  ```\n";
+        let settings = Settings {
+            terminal_size: TerminalSize {
+                width: 12,
+                ..TerminalSize::default()
+            },
+            code_overflow: CodeOverflow::Wrap,
+            ..Settings::default()
+        };
+        let result = String::from_utf8(render_string(markdown, &settings).unwrap()).unwrap();
+        // Unframed code is not indented, so it wraps at the terminal width
+        assert_eq!(
+            result.lines().skip(4).take(2).collect::<Vec<_>>(),
+            vec!["0123456789ab", "\u{21aa} cdefghij"]
+        );
+    }
+
+    #[test]
+    fn framed_code_blocks() {
+        let markdown = "```rust\nfn main() {}\n```\n\n* Item\n\n  ```\n  nested\n  ```\n";
+        let settings = Settings {
+            terminal_size: TerminalSize {
+                width: 20,
+                ..TerminalSize::default()
+            },
+            code_frame: true,
+            ..Settings::default()
+        };
+        let result = String::from_utf8(render_string(markdown, &settings).unwrap()).unwrap();
+        assert_eq!(
+            result,
+            "\u{250c}\u{2500} rust \u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2510}
+\u{2502} fn main() {}     \u{2502}
+\u{2514}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2518}
+
+\u{2022} Item
+
+  \u{250c}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2510}
+  \u{2502} nested         \u{2502}
+  \u{2514}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2500}\u{2518}
+"
+        );
+    }
+
+    #[test]
+    fn number_code_blocks() {
+        let settings = Settings {
+            number_code_blocks: true,
+            ..Settings::default()
+        };
+        let markdown = "```\none\n```\n\n    two\n";
+        let result = String::from_utf8(render_string(markdown, &settings).unwrap()).unwrap();
+        let border = "\u{2500}".repeat(20);
+        assert_eq!(
+            result,
+            format!(
+                "{border} #1\none\n{border}\n\n{border} #2\ntwo\n{border}\n",
+                border = border
+            )
+        );
+
+        // The terminal can't copy
+        let events = Parser::new(markdown).collect::<Vec<_>>();
+        let error = copy_code_block(&mut Vec::new(), &settings, &events, 1).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Terminal dumb can't copy to the clipboard"
+        );
+
+        let settings = Settings {
+            terminal_capabilities: TerminalCapabilities {
+                clipboard: ClipboardCapability::OSC52(OSC52Clipboard),
+                ..TerminalCapabilities::none()
+            },
+            ..settings
+        };
+        let mut sink = Vec::new();
+        copy_code_block(&mut sink, &settings, &events, 2).unwrap();
+        assert_eq!(String::from_utf8(sink).unwrap(), "\x1b]52;c;dHdvCg==\x07");
+
+        // There's no third code block
+        let error = copy_code_block(&mut Vec::new(), &settings, &events, 3).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No code block 3, the document has 2 code blocks"
+        );
+    }
+
+    #[test]
+    fn extract_code_in_language() {
+        let settings = Settings::default();
+        let events = Parser::new(
+            "```sh\necho 1\n```\n
+```sql\nSELECT 1;\n```\n
+```bash\necho 2\n```\n
+    indented
+",
+        )
+        .collect::<Vec<_>>();
+        let extract = |language: Option<&str>| {
+            let mut sink = Vec::new();
+            extract_code(&mut sink, &settings, &events, language).unwrap();
+            String::from_utf8(sink).unwrap()
+        };
+        assert_eq!(extract(None), "echo 1\nSELECT 1;\necho 2\n");
+        assert_eq!(extract(Some("sql")), "SELECT 1;\n");
+        assert_eq!(extract(Some("sh")), "echo 1\necho 2\n");
+        assert_eq!(extract(Some("python")), "");
+    }
+
+    #[test]
+    fn guess_language_of_code_blocks() {
+        let mut settings = Settings {
+            terminal_capabilities: TerminalCapabilities::ansi(),
+            ..Settings::default()
+        };
+        let markdown = "```\n#!/bin/sh\necho $HOME\n```\n\n    just text\n";
+        let render = |settings: &Settings| {
+            String::from_utf8(render_string(markdown, settings).unwrap()).unwrap()
+        };
+        let plain = render(&settings);
+        assert!(plain.contains("\u{1b}[33m#!/bin/sh\necho $HOME\n"));
+        assert!(plain.contains("\u{1b}[33mjust text\n"));
+
+        settings.guess_language = true;
+        let guessed = render(&settings);
+        assert!(!guessed.contains("\u{1b}[33m#!/bin/sh"));
+        assert!(guessed.contains("\u{1b}[32mecho\u{1b}[0m \u{1b}[32m$\u{1b}[0m\u{1b}[34mHOME"));
+        assert!(guessed.contains("\u{1b}[33mjust text\n"));
+    }
+
+    #[test]
+    fn highlight_diffs() {
+        let settings = Settings {
+            terminal_capabilities: TerminalCapabilities::ansi(),
+            ..Settings::default()
+        };
+        let render =
+            |markdown| String::from_utf8(render_string(markdown, &settings).unwrap()).unwrap();
+
+        let plain = render("```diff\n- old\n+ new\n```\n");
+        assert!(plain.contains("\u{1b}[48;5;52;31m-\u{1b}[0m\u{1b}[48;5;52;31m old\u{1b}[0m\n"));
+        assert!(plain.contains("\u{1b}[48;5;22;32m+\u{1b}[0m\u{1b}[48;5;22;32m new\u{1b}[0m\n"));
+
+        // Highlight code in diffs by the file name in headers, or by the info
+        // string.
+        let from_header =
+            render("```diff\n+++ b/main.rs\n@@ -1 +1 @@\n-let x = 1;\n+let x = 2;\n```\n");
+        let from_info = render("```diff rust\n-let x = 1;\n+let x = 2;\n```\n");
+        // Style all headers like hunk headers
+        assert!(from_header.contains("\u{1b}[34m+++\u{1b}[0m\u{1b}[34m b/main.rs\u{1b}[0m\n"));
+        assert!(from_header.contains("\u{1b}[34m@@\u{1b}[0m"));
+        for rendered in &[from_header, from_info] {
+            assert!(rendered.contains("\u{1b}[48;5;52;31m-\u{1b}[0m\u{1b}[48;5;52;34mlet"));
+            assert!(rendered.contains("\u{1b}[48;5;22;32m+\u{1b}[0m\u{1b}[48;5;22;34mlet"));
+        }
+    }
+
+    #[test]
+    fn format_data_in_code_blocks() {
+        let plain = String::from_utf8(
+            render_string("```json\n{\"a\": [1]}\n```\n", &Settings::default()).unwrap(),
+        )
+        .unwrap();
+        assert!(plain.contains("{\"a\": [1]}"));
+
+        let settings = Settings {
+            format_data: true,
+            ..Settings::default()
+        };
+        let result = String::from_utf8(
+            render_string(
+                "```csv\na,bc\n1,2\n```\n\n```json\n{\"a\": [1]}\n```\n\n```json\n{invalid\n```\n",
+                &settings,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let border = "\u{2500}".repeat(20);
+        assert_eq!(
+            result,
+            format!(
+                "{border}
+a \u{2502} bc
+\u{2500}\u{2500}\u{253c}\u{2500}\u{2500}\u{2500}
+1 \u{2502}  2
+{border}
+
+{border}
+{{
+  \"a\": [
+    1
+  ]
+}}
+{border}
+
+{border}
+{{invalid
+{border}
+",
+                border = border
+            )
+        );
     }
 }
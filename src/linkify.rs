@@ -0,0 +1,411 @@
+// Copyright 2020 Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Turn bare URLs and GitHub references in text into links.
+
+use lazy_static::lazy_static;
+use pulldown_cmark::Event::*;
+use pulldown_cmark::{CowStr, Event, LinkType, Tag};
+use regex::{Captures, Regex};
+use std::fmt;
+use std::str::FromStr;
+
+/// A repository on GitHub.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitHubRepository {
+    /// The user or organization owning the repository.
+    pub owner: String,
+    /// The name of the repository.
+    pub name: String,
+}
+
+impl GitHubRepository {
+    /// The URL of this repository.
+    fn url(&self) -> String {
+        format!("https://github.com/{}/{}", self.owner, self.name)
+    }
+}
+
+impl FromStr for GitHubRepository {
+    type Err = String;
+
+    /// Parse a repository from `owner/name`.
+    fn from_str(s: &str) -> Result<GitHubRepository, String> {
+        let mut parts = s.splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(owner), Some(name))
+                if !owner.is_empty() && !name.is_empty() && !name.contains('/') =>
+            {
+                Ok(GitHubRepository {
+                    owner: owner.to_string(),
+                    name: name.to_string(),
+                })
+            }
+            _ => Err(format!("Invalid repository, expected OWNER/NAME: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for GitHubRepository {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.owner, self.name)
+    }
+}
+
+/// Characters GitHub does not include at the end of a bare URL.
+const TRAILING_PUNCTUATION: &[char] =
+    &['.', ',', ':', ';', '!', '?', '"', '\'', ')', ']', '*', '_'];
+
+/// Whether a reference like `#123` or `@user` may follow the `previous` character.
+///
+/// References must not directly follow a word, to skip e.g. email addresses.
+fn may_start_reference(previous: Option<char>) -> bool {
+    match previous {
+        Some(c) => !(c.is_alphanumeric() || "_@/&#".contains(c)),
+        None => true,
+    }
+}
+
+/// Trim a bare `url`.
+///
+/// Drop trailing punctuation, except for a closing parenthesis which closes a
+/// parenthesis in the URL, like in `https://en.wikipedia.org/wiki/Rust_(programming_language)`.
+fn trim_url(url: &str) -> &str {
+    let mut url = url;
+    while let Some(last) = url.chars().last() {
+        let closes_parenthesis =
+            last == ')' && url.matches('(').count() >= url.matches(')').count();
+        if TRAILING_PUNCTUATION.contains(&last) && !closes_parenthesis {
+            url = &url[..url.len() - last.len_utf8()];
+        } else {
+            break;
+        }
+    }
+    url
+}
+
+/// A link found in text.
+struct FoundLink {
+    /// The byte range of the link text.
+    start: usize,
+    end: usize,
+    /// The link type.
+    link_type: LinkType,
+    /// The destination.
+    destination: String,
+    /// The text to show for the link.
+    text: String,
+}
+
+/// Find all links in `text`.
+///
+/// Always find bare URLs; find GitHub references only if `repository` is
+/// given.
+fn find_links(text: &str, repository: Option<&GitHubRepository>) -> Vec<FoundLink> {
+    let previous_char = |start: usize| text[..start].chars().last();
+    LINK_PATTERN
+        .captures_iter(text)
+        .filter_map(|captures: Captures| {
+            if let Some(url) = captures.name("url") {
+                let trimmed = trim_url(url.as_str());
+                let destination = if trimmed.starts_with("www.") {
+                    format!("http://{}", trimmed)
+                } else {
+                    trimmed.to_string()
+                };
+                Some(FoundLink {
+                    start: url.start(),
+                    end: url.start() + trimmed.len(),
+                    link_type: LinkType::Autolink,
+                    destination,
+                    text: trimmed.to_string(),
+                })
+            } else {
+                let repository = repository?;
+                let (m, destination, link_text) = if let Some(issue) = captures.name("issue") {
+                    let destination =
+                        format!("{}/issues/{}", repository.url(), &issue.as_str()[1..]);
+                    (issue, destination, issue.as_str().to_string())
+                } else if let Some(mention) = captures.name("mention") {
+                    let destination = format!("https://github.com/{}", &mention.as_str()[1..]);
+                    (mention, destination, mention.as_str().to_string())
+                } else {
+                    let sha = captures.name("sha")?;
+                    // Require digits and letters, to skip plain numbers as well
+                    // as words like "defaced"
+                    let digits = sha.as_str().chars().filter(char::is_ascii_digit).count();
+                    if digits == 0 || digits == sha.as_str().len() {
+                        return None;
+                    }
+                    let destination = format!("{}/commit/{}", repository.url(), sha.as_str());
+                    (sha, destination, sha.as_str()[..7].to_string())
+                };
+                if may_start_reference(previous_char(m.start())) {
+                    Some(FoundLink {
+                        start: m.start(),
+                        end: m.end(),
+                        link_type: LinkType::Inline,
+                        destination,
+                        text: link_text,
+                    })
+                } else {
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+lazy_static! {
+    /// Bare URLs, and issue references, mentions and commit SHAs on GitHub.
+    static ref LINK_PATTERN: Regex = Regex::new(
+        r"(?P<url>\b(?:https?://|www\.)[^\s<>]+)|(?P<issue>#\d+\b)|(?P<mention>@[A-Za-z0-9][A-Za-z0-9-]*)|(?P<sha>\b[0-9a-f]{7,40}\b)",
+    )
+    .unwrap();
+}
+
+/// Whether `html` opens an HTML link, i.e. an `<a>` tag.
+fn is_html_link_start(html: &str) -> bool {
+    let html = html.trim_start().to_ascii_lowercase();
+    html.starts_with("<a>") || html.starts_with("<a ")
+}
+
+/// Whether `html` closes an HTML link, i.e. a `</a>` tag.
+fn is_html_link_end(html: &str) -> bool {
+    html.trim_start().to_ascii_lowercase().starts_with("</a>")
+}
+
+/// Turn bare URLs in `events` into links.
+///
+/// Find bare `http://`, `https://` and `www.` URLs in text and turn them into
+/// autolinks.  If `repository` is given also link `#123` to issues and commit
+/// SHAs to commits in `repository` and `@user` to GitHub users, like GitHub
+/// does.
+///
+/// Leave text in code blocks, links, images and inline HTML links alone.
+pub fn linkify<'e>(
+    events: Vec<Event<'e>>,
+    repository: Option<&GitHubRepository>,
+) -> Vec<Event<'e>> {
+    let mut result = Vec::with_capacity(events.len());
+    // How deep we are in code blocks, links and images
+    let mut verbatim = 0usize;
+    // Consecutive text events, because the parser splits text at some
+    // punctuation, e.g. at parentheses or underscores within URLs.
+    let mut texts = Vec::new();
+    for event in events {
+        match event {
+            Text(_) if verbatim == 0 => {
+                texts.push(event);
+                continue;
+            }
+            _ if !texts.is_empty() => push_linkified(&mut result, texts.drain(..), repository),
+            _ => {}
+        }
+        match event {
+            Start(Tag::CodeBlock(_)) | Start(Tag::Link(..)) | Start(Tag::Image(..)) => {
+                verbatim += 1
+            }
+            End(Tag::CodeBlock(_)) | End(Tag::Link(..)) | End(Tag::Image(..)) => verbatim -= 1,
+            Html(ref html) if is_html_link_start(html) => verbatim += 1,
+            // Ignore stray closing tags
+            Html(ref html) if is_html_link_end(html) => verbatim = verbatim.saturating_sub(1),
+            _ => {}
+        }
+        result.push(event);
+    }
+    push_linkified(&mut result, texts.drain(..), repository);
+    result
+}
+
+/// Push consecutive `texts` to `events`, with all links found in their text.
+///
+/// Push `texts` as they are if they contain no links.
+fn push_linkified<'e, I>(
+    events: &mut Vec<Event<'e>>,
+    texts: I,
+    repository: Option<&GitHubRepository>,
+) where
+    I: Iterator<Item = Event<'e>>,
+{
+    let texts = texts.collect::<Vec<_>>();
+    let text = texts
+        .iter()
+        .filter_map(|event| match event {
+            Text(text) => Some(text.as_ref()),
+            _ => None,
+        })
+        .collect::<String>();
+    let links = find_links(&text, repository);
+    if links.is_empty() {
+        events.extend(texts);
+        return;
+    }
+    let mut position = 0;
+    for link in links {
+        if position < link.start {
+            events.push(Text(text[position..link.start].to_string().into()));
+        }
+        events.push(Start(Tag::Link(
+            link.link_type,
+            link.destination.clone().into(),
+            CowStr::Borrowed(""),
+        )));
+        events.push(Text(link.text.into()));
+        events.push(End(Tag::Link(
+            link.link_type,
+            link.destination.into(),
+            CowStr::Borrowed(""),
+        )));
+        position = link.end;
+    }
+    if position < text.len() {
+        events.push(Text(text[position..].to_string().into()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use pulldown_cmark::Parser;
+
+    fn links(markdown: &str, repository: Option<&str>) -> Vec<(String, String)> {
+        let repository = repository.map(|repository| repository.parse().unwrap());
+        let events = linkify(Parser::new(markdown).collect(), repository.as_ref());
+        let mut links = Vec::new();
+        let mut current = None;
+        for event in events {
+            match event {
+                Start(Tag::Link(_, destination, _)) => {
+                    current = Some((destination.to_string(), String::new()))
+                }
+                Text(text) => {
+                    if let Some((_, ref mut link_text)) = current {
+                        link_text.push_str(&text)
+                    }
+                }
+                End(Tag::Link(..)) => links.extend(current.take()),
+                _ => {}
+            }
+        }
+        links
+            .into_iter()
+            .map(|(destination, text)| (text, destination))
+            .collect()
+    }
+
+    fn link(text: &str, destination: &str) -> (String, String) {
+        (text.to_string(), destination.to_string())
+    }
+
+    #[test]
+    fn parse_repository() {
+        assert_eq!(
+            "swsnr/mdcat".parse(),
+            Ok(GitHubRepository {
+                owner: "swsnr".to_string(),
+                name: "mdcat".to_string()
+            })
+        );
+        assert!("mdcat".parse::<GitHubRepository>().is_err());
+        assert!("swsnr/".parse::<GitHubRepository>().is_err());
+        assert!("a/b/c".parse::<GitHubRepository>().is_err());
+    }
+
+    #[test]
+    fn linkify_bare_urls() {
+        assert_eq!(
+            links(
+                "See https://example.com/foo, or www.example.com. Also (http://example.com/bar).",
+                None
+            ),
+            vec![
+                link("https://example.com/foo", "https://example.com/foo"),
+                link("www.example.com", "http://www.example.com"),
+                link("http://example.com/bar", "http://example.com/bar"),
+            ]
+        );
+    }
+
+    #[test]
+    fn linkify_url_with_parentheses() {
+        assert_eq!(
+            links(
+                "See https://en.wikipedia.org/wiki/Rust_(programming_language).",
+                None
+            ),
+            vec![link(
+                "https://en.wikipedia.org/wiki/Rust_(programming_language)",
+                "https://en.wikipedia.org/wiki/Rust_(programming_language)"
+            )]
+        );
+    }
+
+    #[test]
+    fn leave_links_and_code_alone() {
+        assert_eq!(
+            links(
+                "[https://example.com](https://example.org) `https://example.com`
+
+```
+https://example.com
+```
+",
+                None
+            ),
+            vec![link("https://example.com", "https://example.org")]
+        );
+    }
+
+    #[test]
+    fn leave_html_links_alone() {
+        assert_eq!(
+            links(
+                "See <a href=\"https://example.org\">https://example.com</a> and https://example.net</a> or https://example.com.",
+                None
+            ),
+            vec![
+                link("https://example.net", "https://example.net"),
+                link("https://example.com", "https://example.com")
+            ]
+        );
+    }
+
+    #[test]
+    fn no_github_references_without_repository() {
+        assert_eq!(links("Fixes #123, thanks @swsnr", None), vec![]);
+    }
+
+    #[test]
+    fn linkify_github_references() {
+        assert_eq!(
+            links(
+                "Fixes #123 in 0123abcd, thanks @swsnr, not foo@example.com or 1234567",
+                Some("swsnr/mdcat")
+            ),
+            vec![
+                link("#123", "https://github.com/swsnr/mdcat/issues/123"),
+                link("0123abc", "https://github.com/swsnr/mdcat/commit/0123abcd"),
+                link("@swsnr", "https://github.com/swsnr"),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_commit_links_for_words() {
+        assert_eq!(
+            links(
+                "The deadbeef was defaced and effaced, but not 0decade",
+                Some("swsnr/mdcat")
+            ),
+            vec![link(
+                "0decade",
+                "https://github.com/swsnr/mdcat/commit/0decade"
+            )]
+        );
+    }
+}
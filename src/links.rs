@@ -0,0 +1,100 @@
+// Copyright 2020 Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Placement and display of link destinations.
+
+use std::str::FromStr;
+
+/// Where to write the destinations of links.
+///
+/// Terminals with inline links show destinations on their own; this only
+/// applies to links the terminal cannot show inline.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LinkPlacement {
+    /// Write the destination in parentheses right after the link text.
+    Inline,
+    /// Write a numbered list of destinations after every paragraph.
+    Paragraph,
+    /// Write a numbered list of destinations before every heading.
+    Section,
+    /// Write a numbered list of all destinations at the end of the document.
+    End,
+    /// Do not write destinations at all.
+    None,
+}
+
+impl Default for LinkPlacement {
+    /// Write destinations before every heading.
+    fn default() -> LinkPlacement {
+        LinkPlacement::Section
+    }
+}
+
+impl FromStr for LinkPlacement {
+    type Err = String;
+
+    /// Parse a link placement from its lowercase name, e.g. `inline`.
+    fn from_str(s: &str) -> Result<LinkPlacement, String> {
+        match s {
+            "inline" => Ok(LinkPlacement::Inline),
+            "paragraph" => Ok(LinkPlacement::Paragraph),
+            "section" => Ok(LinkPlacement::Section),
+            "end" => Ok(LinkPlacement::End),
+            "none" => Ok(LinkPlacement::None),
+            _ => Err(format!("Unknown link placement: {}", s)),
+        }
+    }
+}
+
+/// Shorten `destination` to at most `width` characters for display.
+///
+/// Replace the middle of a longer destination with an ellipsis, to keep the
+/// host and the last part of the path.  Leave destinations of at most `width`
+/// characters as they are.
+pub fn shorten_destination(destination: &str, width: usize) -> String {
+    let length = destination.chars().count();
+    if length <= width || width < 2 {
+        destination.to_string()
+    } else {
+        let tail = (width - 1) / 2;
+        let head = width - 1 - tail;
+        let head_part = destination.chars().take(head).collect::<String>();
+        let tail_part = destination.chars().skip(length - tail).collect::<String>();
+        format!("{}\u{2026}{}", head_part, tail_part)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_link_placement() {
+        assert_eq!("inline".parse(), Ok(LinkPlacement::Inline));
+        assert_eq!("paragraph".parse(), Ok(LinkPlacement::Paragraph));
+        assert_eq!("section".parse(), Ok(LinkPlacement::Section));
+        assert_eq!("end".parse(), Ok(LinkPlacement::End));
+        assert_eq!("none".parse(), Ok(LinkPlacement::None));
+        assert!("bottom".parse::<LinkPlacement>().is_err());
+    }
+
+    #[test]
+    fn shorten_long_destination() {
+        assert_eq!(
+            shorten_destination("https://example.com/some/very/long/path.html", 20),
+            "https://ex\u{2026}path.html"
+        );
+    }
+
+    #[test]
+    fn keep_short_destination() {
+        assert_eq!(
+            shorten_destination("https://example.com", 19),
+            "https://example.com"
+        );
+    }
+}
@@ -8,56 +8,197 @@
 
 //! Show CommonMark documents on TTYs.
 
+use ansi_term::Colour;
 use clap::{value_t, values_t};
 use mdcat::Settings;
 use pulldown_cmark::{Options, Parser};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{stdin, stdout};
-use std::path::PathBuf;
-use syntect::parsing::SyntaxSet;
+use std::path::{Path, PathBuf};
+use url::Url;
 
-use mdcat::{ResourceAccess, TerminalCapabilities, TerminalSize};
+use mdcat::{
+    CodeOverflow, GitHubRepository, HeadingNumbering, HttpHeader, HttpSettings, LinkPlacement,
+    OutlineFormat, ResourceAccess, SectionPattern, TerminalCapabilities, TerminalSize,
+};
+
+/// Whether `filename` denotes a remote document.
+///
+/// Return the URL of the document if `filename` is a HTTP(S) URL.
+fn remote_document_url(filename: &str) -> Option<Url> {
+    Url::parse(filename)
+        .ok()
+        .filter(|url| url.scheme() == "http" || url.scheme() == "https")
+}
 
 /// Read input for `filename`.
 ///
-/// If `filename` is `-` read from standard input, otherwise try to open and
-/// read the given file.
-fn read_input<T: AsRef<str>>(filename: T) -> std::io::Result<(PathBuf, String)> {
+/// If `filename` is `-` read from standard input, if `filename` is a HTTP(S)
+/// URL fetch the document if `settings` permit, otherwise try to open and read
+/// the given file.
+///
+/// Return the base directory and the URL of the document if any, along with
+/// its contents.
+fn read_input<T: AsRef<str>>(
+    filename: T,
+    settings: &Settings,
+) -> Result<(PathBuf, Option<Url>, String), Box<dyn Error>> {
     let cd = std::env::current_dir()?;
     let mut buffer = String::new();
 
     if filename.as_ref() == "-" {
         stdin().read_to_string(&mut buffer)?;
-        Ok((cd, buffer))
+        Ok((cd, None, buffer))
+    } else if let Some(url) = remote_document_url(filename.as_ref()) {
+        if !settings.resource_access.permits(&url) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Remote documents not permitted with --local",
+            )
+            .into());
+        }
+        let contents = mdcat::read_url(&url, &settings.http)?;
+        Ok((cd, Some(url), String::from_utf8(contents)?))
     } else {
         let mut source = File::open(filename.as_ref())?;
         source.read_to_string(&mut buffer)?;
-        let base_dir = cd
-            .join(filename.as_ref())
-            .parent()
-            .map(|p| p.to_path_buf())
-            .unwrap_or(cd);
-        Ok((base_dir, buffer))
+        let path = cd.join(filename.as_ref());
+        let document_url = Url::from_file_path(&path).ok();
+        let base_dir = path.parent().map(|p| p.to_path_buf()).unwrap_or(cd);
+        Ok((base_dir, document_url, buffer))
     }
 }
 
+/// What to write for an input file.
+#[derive(Debug, Clone)]
+enum Output {
+    /// Render the document.
+    Render,
+    /// Dump markdown parser events.
+    DumpEvents,
+    /// Write the outline of the document in the given format.
+    Outline(OutlineFormat),
+    /// Copy the code block with the given number to the clipboard.
+    Copy(usize),
+    /// Write the contents of fenced code blocks, in the given language if any.
+    Extract(Option<String>),
+    /// Write code blocks to the files in their `file=` attribute.
+    ///
+    /// Refuse to tangle remote documents unless permitted.
+    Tangle { allow_remote: bool },
+}
+
+/// Process the given `filename`.
+///
+/// Resolve relative references against `base_url` if given, or else against
+/// the URL of the document.  If given, only process the first `section`
+/// whose heading matches.
+///
+/// Split off front matter, and show it before the document if
+/// `show_front_matter` is set and we render the whole document.
 fn process_file(
     filename: &str,
-    settings: &Settings,
-    dump_events: bool,
+    settings: &mut Settings,
+    base_url: Option<&Url>,
+    section: Option<&SectionPattern>,
+    show_front_matter: bool,
+    output: &Output,
 ) -> Result<(), Box<dyn Error>> {
-    let (base_dir, input) = read_input(filename)?;
+    let (base_dir, document_url, input) = read_input(filename, settings)?;
+    let remote = matches!(document_url, Some(ref url) if url.scheme() != "file");
+    let (front_matter, offset) = match mdcat::split_front_matter(&input) {
+        Some((front_matter, offset)) => (Some(front_matter), offset),
+        None => (None, 0),
+    };
+    // A URL resolves relative references like a web browser, ie, against the
+    // "directory" of the document, and links anchors to the document itself.
+    settings.base_url = base_url.cloned().or(document_url);
     let mut options = Options::empty();
     options.insert(Options::ENABLE_TASKLISTS);
     options.insert(Options::ENABLE_STRIKETHROUGH);
-    let parser = Parser::new_ext(&input, options);
+    // Keep offsets relative to the whole input, for line numbers in outlines
+    let mut events = Parser::new_ext(&input[offset..], options)
+        .into_offset_iter()
+        .map(|(event, range)| (event, range.start + offset..range.end + offset))
+        .collect::<Vec<_>>();
+    // Resolve links in a section against the headings of the whole document
+    let headings = mdcat::table_of_contents(events.iter().map(|(event, _)| event));
+    let mut start = 0;
+    if let Some(section) = section {
+        let range = mdcat::find_section(events.iter().map(|(event, _)| event), section)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("No section matching {}", section),
+                )
+            })?;
+        start = range.start;
+        events = events.drain(range).collect();
+    }
+    if let Output::Render = output {
+        let section_events = events.iter().map(|(event, _)| event);
+        for anchor in mdcat::dangling_anchors(section_events, &headings) {
+            eprintln!("Warning: {}: No heading for link to {}", filename, anchor);
+        }
+    }
 
-    if dump_events {
-        mdcat::dump_events(&mut std::io::stdout(), parser)
-    } else {
-        mdcat::push_tty(settings, &mut stdout(), &base_dir, parser)
+    match output {
+        Output::Render => match front_matter {
+            Some(ref front_matter) if show_front_matter && section.is_none() => {
+                mdcat::write_front_matter(&mut stdout(), settings, front_matter).map_err(Into::into)
+            }
+            _ => Ok(()),
+        }
+        .and_then(|_| {
+            mdcat::push_tty_section(
+                settings,
+                &mut stdout(),
+                &base_dir,
+                events.into_iter().map(|(event, _)| event),
+                &headings,
+                start,
+            )
+        }),
+        Output::DumpEvents => mdcat::dump_events(
+            &mut std::io::stdout(),
+            events.into_iter().map(|(event, _)| event),
+        ),
+        Output::Outline(format) => {
+            mdcat::write_outline(&mut stdout(), settings, &input, &events, *format)
+                .map_err(Into::into)
+        }
+        Output::Copy(number) => mdcat::copy_code_block(
+            &mut stdout(),
+            settings,
+            events.iter().map(|(event, _)| event),
+            *number,
+        ),
+        Output::Extract(language) => mdcat::extract_code(
+            &mut stdout(),
+            settings,
+            events.iter().map(|(event, _)| event),
+            language.as_deref(),
+        ),
+        Output::Tangle { allow_remote } => {
+            if remote && !allow_remote {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Refusing to tangle a remote document without --tangle-remote",
+                )
+                .into());
+            }
+            let blocks = mdcat::code_blocks(events.iter().map(|(event, _)| event));
+            mdcat::tangle(&blocks)?
+                .into_iter()
+                .try_for_each(|(path, contents)| {
+                    std::fs::write(create_tangled_file_dir(&base_dir, path)?, contents)?;
+                    println!("{}", path);
+                    Ok(())
+                })
+        }
     }
     .or_else(|error| match error.downcast_ref::<std::io::Error>() {
         Some(error) if error.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
@@ -65,13 +206,86 @@ fn process_file(
     })
 }
 
+/// Create the directory to tangle the file at `path` beneath `base_dir` to.
+///
+/// Return the path of the file to write.  Fail if the directory lies outside of
+/// `base_dir` after resolving symlinks, or if the file is a symlink, because
+/// symlinks may lead anywhere.
+fn create_tangled_file_dir(base_dir: &Path, path: &str) -> std::io::Result<PathBuf> {
+    let outside = || {
+        std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!(
+                "Refusing to tangle code block to {}: Not beneath the document after resolving symlinks",
+                path
+            ),
+        )
+    };
+    let base_dir = base_dir.canonicalize()?;
+    let file = base_dir.join(path);
+    let parent = file.parent().unwrap_or(&base_dir);
+    // Check existing directories before we create new ones in them
+    if let Some(existing) = parent.ancestors().find(|dir| dir.exists()) {
+        if !existing.canonicalize()?.starts_with(&base_dir) {
+            return Err(outside());
+        }
+    }
+    std::fs::create_dir_all(parent)?;
+    let is_symlink = file
+        .symlink_metadata()
+        .map(|metadata| metadata.file_type().is_symlink())
+        .unwrap_or(false);
+    if is_symlink || !parent.canonicalize()?.starts_with(&base_dir) {
+        Err(outside())
+    } else {
+        Ok(file)
+    }
+}
+
+/// Parse a `colour` from the name of an ANSI colour or an 8-bit colour number.
+fn parse_colour(colour: &str) -> Result<Colour, String> {
+    match colour {
+        "black" => Ok(Colour::Black),
+        "red" => Ok(Colour::Red),
+        "green" => Ok(Colour::Green),
+        "yellow" => Ok(Colour::Yellow),
+        "blue" => Ok(Colour::Blue),
+        "purple" | "magenta" => Ok(Colour::Purple),
+        "cyan" => Ok(Colour::Cyan),
+        "white" => Ok(Colour::White),
+        _ => colour
+            .parse()
+            .map(Colour::Fixed)
+            .map_err(|_| format!("Invalid colour: {}", colour)),
+    }
+}
+
 /// Represent command line arguments.
 struct Arguments {
     filenames: Vec<String>,
     terminal_capabilities: TerminalCapabilities,
     resource_access: ResourceAccess,
+    http: HttpSettings,
+    base_url: Option<Url>,
+    table_of_contents: bool,
+    heading_numbering: Option<HeadingNumbering>,
+    link_placement: LinkPlacement,
+    link_width: Option<usize>,
+    repository: Option<GitHubRepository>,
+    section: Option<SectionPattern>,
+    show_front_matter: bool,
+    interpret_html: bool,
+    line_numbers: bool,
+    code_overflow: CodeOverflow,
+    code_frame: bool,
+    code_background: Option<Colour>,
+    number_code_blocks: bool,
+    syntax_aliases: HashMap<String, String>,
+    guess_language: bool,
+    format_data: bool,
+    syntax_dirs: Vec<PathBuf>,
     columns: usize,
-    dump_events: bool,
+    output: Output,
     detect_only: bool,
     fail_fast: bool,
 }
@@ -95,7 +309,106 @@ impl Arguments {
         }
 
         let filenames = values_t!(matches, "filenames", String)?;
-        let dump_events = matches.is_present("dump_events");
+        let output = if matches.is_present("dump_events") {
+            Output::DumpEvents
+        } else if matches.is_present("outline") {
+            match matches.value_of("outline") {
+                Some("json") => Output::Outline(OutlineFormat::Json),
+                _ => Output::Outline(OutlineFormat::Text),
+            }
+        } else if matches.is_present("copy") {
+            Output::Copy(value_t!(matches, "copy", usize)?)
+        } else if matches.is_present("extract") {
+            Output::Extract(matches.value_of("extract").map(ToString::to_string))
+        } else if matches.is_present("tangle") {
+            Output::Tangle {
+                allow_remote: matches.is_present("tangle_remote"),
+            }
+        } else {
+            Output::Render
+        };
+        let table_of_contents = matches.is_present("table_of_contents");
+        let heading_numbering = if matches.is_present("number_headings") {
+            let start_level = match matches.value_of("number_headings_from") {
+                Some(_) => value_t!(matches, "number_headings_from", u32)?,
+                None => 1,
+            };
+            let format = matches
+                .value_of("number_headings_format")
+                .unwrap_or("{}")
+                .to_string();
+            if !format.contains("{}") {
+                return Err(clap::Error::with_description(
+                    &format!("Heading number format {} does not contain {{}}", format),
+                    clap::ErrorKind::InvalidValue,
+                ));
+            }
+            Some(HeadingNumbering {
+                start_level,
+                format,
+            })
+        } else {
+            None
+        };
+        let link_placement = value_t!(matches, "links", LinkPlacement)?;
+        let link_width = match matches.value_of("link_width") {
+            Some(_) => Some(value_t!(matches, "link_width", usize)?),
+            None => None,
+        };
+        let repository = match matches.value_of("repository") {
+            Some(_) => Some(value_t!(matches, "repository", GitHubRepository)?),
+            None => None,
+        };
+        let section = match matches.value_of("section") {
+            Some(heading) => Some(match matches.value_of("section_match") {
+                Some("regex") => {
+                    SectionPattern::Regex(regex::Regex::new(heading).map_err(|error| {
+                        clap::Error::with_description(
+                            &error.to_string(),
+                            clap::ErrorKind::InvalidValue,
+                        )
+                    })?)
+                }
+                Some("anchor") => SectionPattern::Anchor(heading.to_string()),
+                _ => SectionPattern::Substring(heading.to_string()),
+            }),
+            None => None,
+        };
+        let show_front_matter = matches.value_of("front_matter") != Some("hide");
+        let interpret_html = !matches.is_present("raw_html");
+        let line_numbers = matches.is_present("line_numbers");
+        let code_overflow = value_t!(matches, "code_overflow", CodeOverflow)?;
+        let code_frame = matches.is_present("code_frame");
+        let number_code_blocks = matches.is_present("number_code_blocks");
+        let syntax_aliases = matches
+            .values_of("syntax_aliases")
+            .into_iter()
+            .flatten()
+            .map(|alias| match alias.find('=') {
+                Some(index) if index > 0 && index + 1 < alias.len() => Ok((
+                    alias[..index].trim().to_string(),
+                    alias[index + 1..].trim().to_string(),
+                )),
+                _ => Err(clap::Error::with_description(
+                    &format!("Invalid syntax alias {}, expected NAME=LANGUAGE", alias),
+                    clap::ErrorKind::InvalidValue,
+                )),
+            })
+            .collect::<clap::Result<HashMap<_, _>>>()?;
+        let guess_language = matches.is_present("guess_language");
+        let format_data = matches.is_present("format_data");
+        let syntax_dirs = matches
+            .values_of_os("syntax_dirs")
+            .into_iter()
+            .flatten()
+            .map(PathBuf::from)
+            .collect();
+        let code_background = match matches.value_of("code_background") {
+            Some(colour) => Some(parse_colour(colour).map_err(|error| {
+                clap::Error::with_description(&error, clap::ErrorKind::InvalidValue)
+            })?),
+            None => None,
+        };
         let detect_only = matches.is_present("detect_only");
         let fail_fast = matches.is_present("fail_fast");
         let columns = value_t!(matches, "columns", usize)?;
@@ -104,12 +417,57 @@ impl Arguments {
         } else {
             ResourceAccess::RemoteAllowed
         };
+        // Take headers from $MDCAT_HTTP_HEADERS, one per line, and from the
+        // command line.
+        let headers_from_env = std::env::var("MDCAT_HTTP_HEADERS").unwrap_or_default();
+        let headers = headers_from_env
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .chain(matches.values_of("http_headers").into_iter().flatten())
+            .map(|header| {
+                header.parse::<HttpHeader>().map_err(|error| {
+                    clap::Error::with_description(&error.to_string(), clap::ErrorKind::InvalidValue)
+                })
+            })
+            .collect::<clap::Result<Vec<_>>>()?;
+        let http = HttpSettings {
+            headers,
+            ..HttpSettings::default()
+        };
+        let base_url = match matches.value_of("base_url") {
+            Some(url) => Some(Url::parse(url).map_err(|error| {
+                clap::Error::with_description(
+                    &format!("Invalid base URL {}: {}", url, error),
+                    clap::ErrorKind::InvalidValue,
+                )
+            })?),
+            None => None,
+        };
 
         Ok(Arguments {
             filenames,
             columns,
             resource_access,
-            dump_events,
+            http,
+            base_url,
+            table_of_contents,
+            heading_numbering,
+            link_placement,
+            link_width,
+            repository,
+            section,
+            show_front_matter,
+            interpret_html,
+            line_numbers,
+            code_overflow,
+            code_frame,
+            code_background,
+            number_code_blocks,
+            syntax_aliases,
+            guess_language,
+            format_data,
+            syntax_dirs,
+            output,
             detect_only,
             fail_fast,
             terminal_capabilities,
@@ -117,6 +475,22 @@ impl Arguments {
     }
 }
 
+/// The directory to cache data of mdcat in, if any.
+///
+/// Use `$XDG_CACHE_HOME/mdcat`, or `~/.cache/mdcat`, or on Windows
+/// `%LOCALAPPDATA%\mdcat`.
+fn cache_dir() -> Option<PathBuf> {
+    let non_empty = |variable| std::env::var_os(variable).filter(|value| !value.is_empty());
+    let base = if cfg!(windows) {
+        non_empty("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        non_empty("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| non_empty("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    base.map(|base| base.join("mdcat"))
+}
+
 fn main() {
     use clap::*;
     let size = TerminalSize::detect().unwrap_or_default();
@@ -149,7 +523,10 @@ Report issues to <https://github.com/lunaryorn/mdcat>.",
         .arg(
             Arg::with_name("filenames")
                 .multiple(true)
-                .help("The file to read.  If - read from standard input instead")
+                .help(
+                    "The file to read.  If - read from standard input instead.  \
+Fetch HTTP(S) URLs unless --local is given",
+                )
                 .default_value("-"),
         )
         .arg(
@@ -165,12 +542,259 @@ Report issues to <https://github.com/lunaryorn/mdcat>.",
                 .help("Maximum number of columns to use for output")
                 .default_value(&columns),
         )
+        .arg(
+            Arg::with_name("table_of_contents")
+                .long("toc")
+                .help("Show a table of contents before the document"),
+        )
+        .arg(
+            Arg::with_name("number_headings")
+                .long("number-headings")
+                .help("Number headings, in the document, the table of contents and the outline"),
+        )
+        .arg(
+            Arg::with_name("number_headings_from")
+                .long("number-headings-from")
+                .value_name("LEVEL")
+                .takes_value(true)
+                .possible_values(&["1", "2", "3", "4", "5", "6"])
+                .requires("number_headings")
+                .help("Only number headings of LEVEL and below, from 1 by default"),
+        )
+        .arg(
+            Arg::with_name("number_headings_format")
+                .long("number-headings-format")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .requires("number_headings")
+                .help("Format heading numbers, replacing {} with the number, e.g. \"{}.\""),
+        )
+        .arg(
+            Arg::with_name("links")
+                .long("links")
+                .value_name("PLACEMENT")
+                .takes_value(true)
+                .possible_values(&["inline", "paragraph", "section", "end", "none"])
+                .default_value("section")
+                .help(
+                    "Where to show link destinations the terminal cannot link inline: \
+After the link text, after each paragraph, before each heading, at the end of \
+the document, or nowhere",
+                ),
+        )
+        .arg(
+            Arg::with_name("link_width")
+                .long("link-width")
+                .value_name("WIDTH")
+                .takes_value(true)
+                .help("Shorten link destinations longer than WIDTH characters"),
+        )
+        .arg(
+            Arg::with_name("repository")
+                .long("repo")
+                .value_name("OWNER/NAME")
+                .takes_value(true)
+                .help(
+                    "Link issue references like #123, mentions like @user and \
+commit SHAs to the GitHub repository OWNER/NAME",
+                ),
+        )
+        .arg(
+            Arg::with_name("section")
+                .long("section")
+                .value_name("HEADING")
+                .takes_value(true)
+                .help(
+                    "Only show the first section whose heading matches HEADING, \
+up to the next heading of the same or a higher level",
+                ),
+        )
+        .arg(
+            Arg::with_name("section_match")
+                .long("section-match")
+                .value_name("MATCH")
+                .takes_value(true)
+                .possible_values(&["substring", "regex", "anchor"])
+                .default_value("substring")
+                .help(
+                    "How to match the heading of --section: Headings containing \
+HEADING, matching HEADING as regular expression, or with the same anchor as HEADING",
+                ),
+        )
+        .arg(
+            Arg::with_name("outline")
+                .long("outline")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .empty_values(true)
+                .possible_values(&["text", "json"])
+                .help("Only show the headings of the document with their line numbers, as text or JSON"),
+        )
+        .arg(
+            Arg::with_name("front_matter")
+                .long("front-matter")
+                .value_name("DISPLAY")
+                .takes_value(true)
+                .possible_values(&["show", "hide"])
+                .default_value("show")
+                .help("Show YAML or TOML front matter as keys and values, or hide it"),
+        )
+        .arg(
+            Arg::with_name("raw_html")
+                .long("raw-html")
+                .help("Show HTML as it is instead of interpreting common tags"),
+        )
+        .arg(
+            Arg::with_name("line_numbers")
+                .long("line-numbers")
+                .help(
+                    "Number the lines of all code blocks.  Code blocks can also ask \
+for line numbers with linenos and start=N in their info string, e.g. rust,linenos",
+                ),
+        )
+        .arg(
+            Arg::with_name("code_overflow")
+                .long("code-overflow")
+                .value_name("OVERFLOW")
+                .takes_value(true)
+                .possible_values(&["wrap", "truncate", "none"])
+                .default_value("none")
+                .help(
+                    "What to do with lines of code wider than the terminal: Wrap \
+them, cut them off, or leave them to the terminal",
+                ),
+        )
+        .arg(
+            Arg::with_name("code_frame")
+                .long("code-frame")
+                .help("Draw a frame around code blocks, with their language at the top"),
+        )
+        .arg(
+            Arg::with_name("code_background")
+                .long("code-background")
+                .value_name("COLOUR")
+                .takes_value(true)
+                .requires("code_frame")
+                .help(
+                    "Fill framed code blocks with COLOUR, either the name of an \
+ANSI colour like blue or an 8-bit colour number from 0 to 255",
+                ),
+        )
+        .arg(
+            Arg::with_name("syntax_aliases")
+                .long("syntax-alias")
+                .value_name("NAME=LANGUAGE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Highlight code blocks in NAME like LANGUAGE, a language \
+token like rs or the name of a syntax",
+                ),
+        )
+        .arg(
+            Arg::with_name("guess_language")
+                .long("guess-language")
+                .help(
+                    "Guess the language of code blocks without language, to \
+highlight them",
+                ),
+        )
+        .arg(
+            Arg::with_name("format_data")
+                .long("format-data")
+                .help(
+                    "Show csv and tsv code blocks as aligned tables, and \
+re-indent json code blocks",
+                ),
+        )
+        .arg(
+            Arg::with_name("syntax_dirs")
+                .long("syntax-dir")
+                .value_name("DIR")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Load additional syntax definitions for code blocks from \
+.sublime-syntax files in DIR",
+                ),
+        )
+        .arg(
+            Arg::with_name("number_code_blocks")
+                .long("number-code-blocks")
+                .help("Show the number of each code block, to copy it with --copy"),
+        )
+        .arg(
+            Arg::with_name("copy")
+                .long("copy")
+                .value_name("N")
+                .takes_value(true)
+                .conflicts_with_all(&["outline", "dump_events"])
+                .help(
+                    "Only copy the contents of code block N to the clipboard, \
+counting from 1, in terminals which support OSC 52",
+                ),
+        )
+        .arg(
+            Arg::with_name("extract")
+                .long("extract")
+                .value_name("LANGUAGE")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .conflicts_with_all(&["outline", "dump_events", "copy"])
+                .help(
+                    "Only write the contents of fenced code blocks as they are, \
+only of code blocks in LANGUAGE if given",
+                ),
+        )
+        .arg(
+            Arg::with_name("tangle")
+                .long("tangle")
+                .conflicts_with_all(&["outline", "dump_events", "copy", "extract"])
+                .help(
+                    "Write the contents of code blocks with file=PATH in their \
+info string to PATH, relative to the document, and list all written files",
+                ),
+        )
+        .arg(
+            Arg::with_name("tangle_remote")
+                .long("tangle-remote")
+                .requires("tangle")
+                .help("Also tangle remote documents, to the current directory"),
+        )
         .arg(
             Arg::with_name("local_only")
                 .short("l")
                 .long("local")
                 .help("Do not load remote resources like images"),
         )
+        .arg(
+            Arg::with_name("base_url")
+                .long("base-url")
+                .value_name("URL")
+                .takes_value(true)
+                .help(
+                    "Resolve relative links and images against URL instead of \
+the URL or path of the document",
+                ),
+        )
+        .arg(
+            Arg::with_name("http_headers")
+                .long("http-header")
+                .value_name("[HOST[:PORT]=]NAME: VALUE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Send an HTTP header when fetching remote resources, \
+only to HOST if given, and never along redirects to other hosts.  Also read from \
+$MDCAT_HTTP_HEADERS, one header per line",
+                ),
+        )
         .arg(
             Arg::with_name("dump_events")
                 .long("dump-events")
@@ -204,36 +828,84 @@ Report issues to <https://github.com/lunaryorn/mdcat>.",
     } else {
         let Arguments {
             filenames,
-            dump_events,
+            output,
             fail_fast,
             terminal_capabilities,
             columns,
             resource_access,
+            http,
+            base_url,
+            table_of_contents,
+            heading_numbering,
+            link_placement,
+            link_width,
+            repository,
+            section,
+            show_front_matter,
+            interpret_html,
+            line_numbers,
+            code_overflow,
+            code_frame,
+            code_background,
+            number_code_blocks,
+            syntax_aliases,
+            guess_language,
+            format_data,
+            syntax_dirs,
             ..
         } = arguments;
 
-        let settings = Settings {
+        let syntax_set = mdcat::load_syntax_set(&syntax_dirs, cache_dir().as_deref())
+            .unwrap_or_else(|error| {
+                eprintln!("Error: {}", error);
+                std::process::exit(1);
+            });
+
+        let mut settings = Settings {
             terminal_capabilities,
             terminal_size: TerminalSize {
                 width: columns,
                 ..size
             },
             resource_access,
-            syntax_set: SyntaxSet::load_defaults_newlines(),
+            http,
+            table_of_contents,
+            heading_numbering,
+            link_placement,
+            link_width,
+            repository,
+            interpret_html,
+            line_numbers,
+            code_overflow,
+            code_frame,
+            code_background,
+            number_code_blocks,
+            syntax_aliases,
+            guess_language,
+            format_data,
+            base_url: None,
+            syntax_set,
         };
         let exit_code = filenames
             .iter()
             .try_fold(0, |code, filename| {
-                process_file(filename, &settings, dump_events)
-                    .map(|_| code)
-                    .or_else(|error| {
-                        eprintln!("Error: {}: {}", filename, error);
-                        if fail_fast {
-                            Err(error)
-                        } else {
-                            Ok(1)
-                        }
-                    })
+                process_file(
+                    filename,
+                    &mut settings,
+                    base_url.as_ref(),
+                    section.as_ref(),
+                    show_front_matter,
+                    &output,
+                )
+                .map(|_| code)
+                .or_else(|error| {
+                    eprintln!("Error: {}: {}", filename, error);
+                    if fail_fast {
+                        Err(error)
+                    } else {
+                        Ok(1)
+                    }
+                })
             })
             .unwrap_or(1);
         std::process::exit(exit_code);
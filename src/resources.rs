@@ -6,11 +6,20 @@
 
 //! Access to resources referenced from markdown documents.
 
+#[cfg(feature = "reqwest")]
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use url::Url;
 
+/// The maximum number of resources we read at the same time.
+const MAX_CONCURRENT_READS: usize = 8;
+
 /// What kind of resources mdcat may access when rendering.
 ///
 /// This struct denotes whether mdcat shows inline images from remote URLs or
@@ -39,9 +48,264 @@ fn is_local(url: &Url) -> bool {
     url.scheme() == "file" && url.to_file_path().is_ok()
 }
 
+/// An HTTP header to send along with requests for remote resources.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpHeader {
+    /// The host to send this header to, or `None` to send it to all hosts.
+    pub host: Option<String>,
+    /// The name of the header.
+    pub name: String,
+    /// The value of the header.
+    pub value: String,
+}
+
+impl HttpHeader {
+    /// Whether to send this header along with a request for `url`.
+    ///
+    /// Headers without a host apply to every URL; otherwise the host of `url`
+    /// must match the host of the header exactly, ignoring case, and the port
+    /// of `url` the port of the header, if the header has a port.
+    pub fn applies_to(&self, url: &Url) -> bool {
+        match (&self.host, url.host_str()) {
+            (None, _) => true,
+            (Some(host), Some(url_host)) => {
+                let (host, port) = split_port(host);
+                let port_matches = match port {
+                    Some(port) => port.parse().ok() == url.port_or_known_default(),
+                    None => true,
+                };
+                host.eq_ignore_ascii_case(url_host) && port_matches
+            }
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// Split the port off `host`, as in `example.com:8080`.
+///
+/// IPv6 addresses need brackets, as in `[::1]:8080`.
+fn split_port(host: &str) -> (&str, Option<&str>) {
+    match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => (&host[..colon], Some(&host[colon + 1..])),
+        _ => (host, None),
+    }
+}
+
+/// Whether `text` is a host with an optional port, like `example.com:8080`.
+fn is_host_with_port(text: &str) -> bool {
+    let (host, port) = split_port(text);
+    let valid_port = match port {
+        Some(port) => port.parse::<u16>().is_ok(),
+        None => true,
+    };
+    !host.is_empty()
+        && !host.contains(char::is_whitespace)
+        && (host.starts_with('[') || !host.contains(':'))
+        && valid_port
+}
+
+impl FromStr for HttpHeader {
+    type Err = Error;
+
+    /// Parse a header from `[HOST[:PORT]=]NAME: VALUE`.
+    ///
+    /// Header names may not contain `=`, so we treat everything before the
+    /// first `=` as the host, if it looks like a host and a header follows.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Invalid HTTP header, expected [HOST[:PORT]=]NAME: VALUE: {}",
+                    s
+                ),
+            )
+        };
+        let (host, header) = match s.find('=') {
+            Some(equals) if is_host_with_port(s[..equals].trim()) && s[equals..].contains(':') => {
+                (Some(s[..equals].trim()), &s[equals + 1..])
+            }
+            _ => (None, s),
+        };
+        let colon = header.find(':').ok_or_else(invalid)?;
+        let name = header[..colon].trim();
+        if name.is_empty() || name.contains(|c: char| c == '=' || c.is_whitespace()) {
+            Err(invalid())
+        } else {
+            Ok(HttpHeader {
+                host: host.map(ToOwned::to_owned),
+                name: name.to_string(),
+                value: header[colon + 1..].trim().to_string(),
+            })
+        }
+    }
+}
+
+/// Settings for HTTP requests for remote resources.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpSettings {
+    /// The user agent to send.
+    pub user_agent: String,
+    /// Additional headers to send.
+    pub headers: Vec<HttpHeader>,
+}
+
+impl Default for HttpSettings {
+    /// Identify as mdcat, and send no additional headers.
+    fn default() -> HttpSettings {
+        HttpSettings {
+            user_agent: concat!("mdcat/", env!("CARGO_PKG_VERSION")).to_string(),
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl HttpSettings {
+    /// All headers to send along with a request for `url`.
+    pub fn headers_for<'a>(&'a self, url: &'a Url) -> impl Iterator<Item = &'a HttpHeader> {
+        self.headers
+            .iter()
+            .filter(move |header| header.applies_to(url))
+    }
+
+    /// Whether we send a header only meant for the host of `url` along with a
+    /// request for `url`.
+    ///
+    /// Requests with such headers must not follow redirects to other hosts.
+    fn has_host_headers_for(&self, url: &Url) -> bool {
+        self.headers_for(url).any(|header| header.host.is_some())
+    }
+}
+
+/// Get the value of the first of the given environment `variables` which is set
+/// and not empty.
+fn first_env_var<F>(env: &F, variables: &[&str]) -> Option<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    variables
+        .iter()
+        .filter_map(|variable| env(variable))
+        .find(|value| !value.is_empty())
+}
+
+/// Get the proxy to use for `url` from the environment.
+///
+/// Look at `$scheme_proxy` and `$ALL_PROXY`, in lower and upper case, and
+/// return the first proxy set, unless the host of `url` matches `$NO_PROXY`.
+///
+/// `$NO_PROXY` is a comma-separated list of host names which match the host
+/// itself and all of its subdomains, or `*` to match all hosts.
+///
+/// `env` looks up environment variables.
+fn proxy_from_env<F>(url: &Url, env: F) -> Option<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let host = url.host_str()?;
+    let no_proxy = first_env_var(&env, &["no_proxy", "NO_PROXY"]).unwrap_or_default();
+    let excluded = no_proxy
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches('.'))
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            entry == "*"
+                || host.eq_ignore_ascii_case(entry)
+                || host
+                    .to_ascii_lowercase()
+                    .ends_with(&format!(".{}", entry.to_ascii_lowercase()))
+        });
+    if excluded {
+        None
+    } else {
+        let scheme_proxy = format!("{}_proxy", url.scheme());
+        first_env_var(
+            &env,
+            &[
+                &scheme_proxy,
+                &scheme_proxy.to_uppercase(),
+                "all_proxy",
+                "ALL_PROXY",
+            ],
+        )
+    }
+}
+
+/// Get the proxy to use for `url` from the process environment.
+fn proxy_for(url: &Url) -> Option<String> {
+    proxy_from_env(url, |variable| std::env::var(variable).ok())
+}
+
+/// The maximum number of redirects we follow.
 #[cfg(feature = "reqwest")]
-fn fetch_http(url: &Url) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let mut response = reqwest::blocking::get(url.clone())?;
+const MAX_REDIRECTS: usize = 10;
+
+#[cfg(feature = "reqwest")]
+lazy_static! {
+    /// HTTP clients for all HTTP settings we used so far.
+    static ref HTTP_CLIENTS: Mutex<Vec<(HttpSettings, reqwest::blocking::Client)>> =
+        Mutex::new(Vec::new());
+}
+
+/// Get an HTTP client for the given `http` settings.
+///
+/// Build a client once for every distinct `http` settings, and reuse it for
+/// all further requests with the same settings.
+#[cfg(feature = "reqwest")]
+fn http_client(http: &HttpSettings) -> reqwest::Result<reqwest::blocking::Client> {
+    let mut clients = HTTP_CLIENTS
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+    if let Some((_, client)) = clients.iter().find(|(settings, _)| settings == http) {
+        return Ok(client.clone());
+    }
+    let settings = http.clone();
+    let redirect = reqwest::redirect::Policy::custom(move |attempt| {
+        let first = &attempt.previous()[0];
+        if settings.has_host_headers_for(first) && first.host_str() != attempt.url().host_str() {
+            let message = format!(
+                "Refusing to follow redirect from {} to {} with headers for {} only",
+                first,
+                attempt.url(),
+                first.host_str().unwrap_or_default()
+            );
+            attempt.error(message)
+        } else if attempt.previous().len() > MAX_REDIRECTS {
+            attempt.error("Too many redirects")
+        } else {
+            attempt.follow()
+        }
+    });
+    let client = reqwest::blocking::Client::builder()
+        .user_agent(http.user_agent.as_str())
+        .redirect(redirect)
+        // Resolve proxies on our own, to handle proxy variables exactly like
+        // curl, which defaults to HTTP proxies
+        .proxy(reqwest::Proxy::custom(|url| {
+            proxy_for(url).and_then(|proxy| {
+                if proxy.contains("://") {
+                    Url::parse(&proxy).ok()
+                } else {
+                    Url::parse(&format!("http://{}", proxy)).ok()
+                }
+            })
+        }))
+        .build()?;
+    clients.push((http.clone(), client.clone()));
+    Ok(client)
+}
+
+/// Fetch `url` with the given `http` settings.
+///
+/// Follow redirects, but only to the same host if we send headers for the
+/// host of `url` only.
+#[cfg(feature = "reqwest")]
+fn fetch_http(url: &Url, http: &HttpSettings) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut request = http_client(http)?.get(url.clone());
+    for header in http.headers_for(url) {
+        request = request.header(header.name.as_str(), header.value.as_str());
+    }
+    let mut response = request.send()?;
     if response.status().is_success() {
         let mut buffer = Vec::new();
         response.read_to_end(&mut buffer)?;
@@ -55,12 +319,71 @@ fn fetch_http(url: &Url) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     }
 }
 
+/// Quote `value` as a string in a curl config file.
+#[cfg(any(test, not(feature = "reqwest")))]
+fn quote_curl_config(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Fetch `url` with curl, with the given `http` settings.
+///
+/// Pass headers to curl through standard input, because other users can see
+/// the command line of curl.
+///
+/// Follow redirects, unless we send headers for the host of `url` only;
+/// curl can't check the host of each redirect.
 #[cfg(not(feature = "reqwest"))]
-fn fetch_http(url: &Url) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let output = std::process::Command::new("curl")
+fn fetch_http(url: &Url, http: &HttpSettings) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    use std::process::{Command, Stdio};
+
+    let mut command = Command::new("curl");
+    command
         .arg("-fsSL")
+        .arg("--user-agent")
+        .arg(&http.user_agent)
+        .arg("--config")
+        .arg("-");
+    if http.has_host_headers_for(url) {
+        command.arg("--max-redirs").arg("0");
+    }
+    // Pass the proxy explicitly, to handle proxy variables exactly like reqwest
+    match proxy_for(url) {
+        Some(proxy) => command.arg("--proxy").arg(proxy),
+        None => command.arg("--noproxy").arg("*"),
+    };
+    let mut child = command
         .arg(url.to_string())
-        .output()?;
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let config = http
+        .headers_for(url)
+        .map(|header| {
+            let header = format!("{}: {}", header.name, header.value);
+            format!("header = {}\n", quote_curl_config(&header))
+        })
+        .collect::<String>();
+    // Close standard input after writing the config, to let curl continue
+    let written = match child.stdin.take() {
+        Some(mut stdin) => stdin.write_all(config.as_bytes()),
+        None => Ok(()),
+    };
+    let output = child.wait_with_output()?;
+    written?;
 
     if output.status.success() {
         Ok(output.stdout)
@@ -84,8 +407,8 @@ fn fetch_http(url: &Url) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
 ///
 /// We currently support `file:` URLs which the underlying operation system can
 /// read (local on UNIX, UNC paths on Windows), and HTTP(S) URLs if enabled at
-/// build system.
-pub fn read_url(url: &Url) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+/// build system.  Requests for HTTP(S) URLs use the given `http` settings.
+pub fn read_url(url: &Url, http: &HttpSettings) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     match url.scheme() {
         "file" => match url.to_file_path() {
             Ok(path) => {
@@ -99,7 +422,7 @@ pub fn read_url(url: &Url) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
             )
             .into()),
         },
-        "http" | "https" => fetch_http(url),
+        "http" | "https" => fetch_http(url, http),
         _ => Err(Error::new(
             ErrorKind::InvalidInput,
             format!("Protocol of URL {} not supported", url),
@@ -108,6 +431,59 @@ pub fn read_url(url: &Url) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     }
 }
 
+/// Read all `urls` concurrently with `read`.
+///
+/// Call `read` for every distinct URL in `urls` on a bounded pool of worker
+/// threads, and return the result of `read` for each URL.
+///
+/// `read` must not fail; to keep failures map errors to `None` or to a
+/// `Send`able value.
+pub fn read_all_concurrently<I, T, F>(urls: I, read: F) -> HashMap<Url, T>
+where
+    I: IntoIterator<Item = Url>,
+    T: Send + 'static,
+    F: Fn(&Url) -> T + Send + Sync + 'static,
+{
+    let mut seen = HashSet::new();
+    let queue = urls
+        .into_iter()
+        .filter(|url| seen.insert(url.clone()))
+        .collect::<VecDeque<_>>();
+    let workers = queue.len().min(MAX_CONCURRENT_READS);
+    let queue = Arc::new(Mutex::new(queue));
+    let read = Arc::new(read);
+    let (sender, receiver) = mpsc::channel();
+    let handles = (0..workers)
+        .map(|_| {
+            let queue = queue.clone();
+            let read = read.clone();
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                // Take the lock only to pop the next URL, not while reading it.
+                let next = queue.lock().ok().and_then(|mut queue| queue.pop_front());
+                match next {
+                    Some(url) => {
+                        let result = read(&url);
+                        if sender.send((url, result)).is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    // Drop our own sender, to end the iteration below once all workers are done
+    drop(sender);
+    let results = receiver.iter().collect();
+    for handle in handles {
+        // A panicking worker just leaves its URL out of the results; callers
+        // then read it again on their own.
+        handle.join().ok();
+    }
+    results
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -136,12 +512,188 @@ mod tests {
         assert!(ResourceAccess::RemoteAllowed.permits(&resource));
     }
 
+    #[test]
+    fn parse_http_header() {
+        let header = "Authorization: token foo:bar"
+            .parse::<HttpHeader>()
+            .unwrap();
+        assert_eq!(
+            header,
+            HttpHeader {
+                host: None,
+                name: "Authorization".to_string(),
+                value: "token foo:bar".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_http_header_for_host() {
+        let header = "gitlab.example.com=PRIVATE-TOKEN: a=b"
+            .parse::<HttpHeader>()
+            .unwrap();
+        assert_eq!(
+            header,
+            HttpHeader {
+                host: Some("gitlab.example.com".to_string()),
+                name: "PRIVATE-TOKEN".to_string(),
+                value: "a=b".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_http_header_for_host_and_port() {
+        let header = "localhost:8080=X-Token: a:b=c"
+            .parse::<HttpHeader>()
+            .unwrap();
+        assert_eq!(header.host, Some("localhost:8080".to_string()));
+        assert_eq!(header.name, "X-Token");
+        assert_eq!(header.value, "a:b=c");
+        assert!(header.applies_to(&Url::parse("http://localhost:8080/foo").unwrap()));
+        assert!(!header.applies_to(&Url::parse("http://localhost/foo").unwrap()));
+
+        let header = "[::1]:443=X-Token: foo".parse::<HttpHeader>().unwrap();
+        assert_eq!(header.host, Some("[::1]:443".to_string()));
+        assert!(header.applies_to(&Url::parse("https://[::1]/foo").unwrap()));
+
+        let header = "Authorization: token a=b".parse::<HttpHeader>().unwrap();
+        assert_eq!(header.host, None);
+        assert_eq!(header.value, "token a=b");
+    }
+
+    #[test]
+    fn parse_invalid_http_header() {
+        for header in &[
+            "Authorization",
+            ": foo",
+            "=Authorization: foo",
+            "host=: foo",
+        ] {
+            assert!(
+                header.parse::<HttpHeader>().is_err(),
+                "Parsed invalid header {}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn http_header_applies_to_matching_host_only() {
+        let header = "GitHub.com=Authorization: token foo"
+            .parse::<HttpHeader>()
+            .unwrap();
+        assert!(header.applies_to(&Url::parse("https://github.com/foo").unwrap()));
+        assert!(!header.applies_to(&Url::parse("https://api.github.com/foo").unwrap()));
+        assert!(!header.applies_to(&Url::parse("https://example.com/foo").unwrap()));
+
+        let header = "Authorization: token foo".parse::<HttpHeader>().unwrap();
+        assert!(header.applies_to(&Url::parse("https://example.com/foo").unwrap()));
+    }
+
+    #[test]
+    fn quote_strings_for_curl_config() {
+        assert_eq!(
+            quote_curl_config("Authorization: \"a\\b\"\n"),
+            "\"Authorization: \\\"a\\\\b\\\"\\n\""
+        );
+    }
+
+    fn env_from(
+        variables: &'static [(&'static str, &'static str)],
+    ) -> impl Fn(&str) -> Option<String> {
+        move |name| {
+            variables
+                .iter()
+                .find(|(variable, _)| *variable == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn proxy_from_env_prefers_scheme_proxy() {
+        let env = env_from(&[
+            ("https_proxy", "http://https.proxy:8080"),
+            ("HTTP_PROXY", "http://http.proxy:8080"),
+            ("all_proxy", "http://all.proxy:8080"),
+        ]);
+        let https = Url::parse("https://example.com/foo").unwrap();
+        let http = Url::parse("http://example.com/foo").unwrap();
+        let ftp = Url::parse("ftp://example.com/foo").unwrap();
+        assert_eq!(
+            proxy_from_env(&https, &env),
+            Some("http://https.proxy:8080".to_string())
+        );
+        assert_eq!(
+            proxy_from_env(&http, &env),
+            Some("http://http.proxy:8080".to_string())
+        );
+        assert_eq!(
+            proxy_from_env(&ftp, &env),
+            Some("http://all.proxy:8080".to_string())
+        );
+    }
+
+    #[test]
+    fn proxy_from_env_respects_no_proxy() {
+        let env = env_from(&[
+            ("ALL_PROXY", "http://proxy:8080"),
+            ("NO_PROXY", "localhost, .example.com"),
+        ]);
+        for url in &[
+            "http://localhost/foo",
+            "https://example.com/foo",
+            "https://www.example.com/foo",
+        ] {
+            assert_eq!(proxy_from_env(&Url::parse(url).unwrap(), &env), None);
+        }
+        assert_eq!(
+            proxy_from_env(&Url::parse("https://notexample.com").unwrap(), &env),
+            Some("http://proxy:8080".to_string())
+        );
+        let env = env_from(&[("ALL_PROXY", "http://proxy:8080"), ("no_proxy", "*")]);
+        assert_eq!(
+            proxy_from_env(&Url::parse("https://example.com").unwrap(), &env),
+            None
+        );
+    }
+
+    #[test]
+    fn read_all_concurrently_reads_every_distinct_url_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let reads = Arc::new(AtomicUsize::new(0));
+        let urls = (0..20)
+            .map(|i| {
+                format!("https://example.com/{}", i % 10)
+                    .parse::<Url>()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let counter = reads.clone();
+        let results = read_all_concurrently(urls.clone(), move |url| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            url.path().to_string()
+        });
+        assert_eq!(reads.load(Ordering::SeqCst), 10);
+        assert_eq!(results.len(), 10);
+        for url in urls {
+            assert_eq!(results[&url], url.path());
+        }
+    }
+
+    #[test]
+    fn read_all_concurrently_without_urls() {
+        let results = read_all_concurrently(Vec::new(), |url| url.clone());
+        assert!(results.is_empty());
+    }
+
     #[test]
     fn read_url_with_http_url_fails_when_status_404() {
         let url = "https://eu.httpbin.org/status/404"
             .parse::<url::Url>()
             .unwrap();
-        let result = read_url(&url);
+        let result = read_url(&url, &HttpSettings::default());
         assert!(result.is_err(), "Unexpected success: {:?}", result);
         let error = result.unwrap_err().to_string();
         if cfg!(feature = "reqwest") {
@@ -168,7 +720,7 @@ mod tests {
         let url = "https://eu.httpbin.org/bytes/100"
             .parse::<url::Url>()
             .unwrap();
-        let result = read_url(&url);
+        let result = read_url(&url, &HttpSettings::default());
         assert!(result.is_ok(), "Unexpected error: {:?}", result);
         assert_eq!(result.unwrap().len(), 100);
     }
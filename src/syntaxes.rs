@@ -0,0 +1,257 @@
+// Copyright 2020 Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Syntax definitions for highlighting.
+
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use syntect::dumps::{dump_to_file, from_dump_file};
+use syntect::parsing::SyntaxSet;
+
+/// How many caches of syntaxes to keep.
+///
+/// Keep a few caches, so that switching between different syntax directories
+/// doesn't rebuild the cache every time.
+const MAX_CACHES: usize = 4;
+
+/// Load the default syntaxes together with all syntax definitions in `dirs`.
+///
+/// Merge all `.sublime-syntax` files in `dirs` and their subdirectories into
+/// the default syntaxes of syntect; syntaxes from `dirs` take precedence.
+///
+/// If `cache_dir` is given cache the merged syntaxes as a binary dump in
+/// `cache_dir`, and load them from the cache as long as no syntax file
+/// changed, because compiling syntax definitions is slow.  Ignore errors
+/// when reading or writing the cache.
+///
+/// Without `dirs` just load the default syntaxes.
+pub fn load_syntax_set<P: AsRef<Path>>(
+    dirs: &[P],
+    cache_dir: Option<&Path>,
+) -> Result<SyntaxSet, Box<dyn Error>> {
+    if dirs.is_empty() {
+        return Ok(SyntaxSet::load_defaults_newlines());
+    }
+    let mut files = Vec::new();
+    for dir in dirs {
+        find_syntax_files(dir.as_ref(), &mut files).map_err(|error| {
+            format!(
+                "Failed to read syntaxes from {}: {}",
+                dir.as_ref().display(),
+                error
+            )
+        })?;
+    }
+    let cache_file = match cache_dir {
+        Some(cache_dir) => {
+            Some(cache_dir.join(format!("syntaxes-{:016x}.packdump", cache_key(&files)?)))
+        }
+        None => None,
+    };
+    if let Some(syntax_set) = cache_file
+        .as_ref()
+        .and_then(|file| from_dump_file(file).ok())
+    {
+        return Ok(syntax_set);
+    }
+
+    let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+    for dir in dirs {
+        builder.add_from_folder(dir, true).map_err(|error| {
+            format!(
+                "Failed to load syntaxes from {}: {}",
+                dir.as_ref().display(),
+                error
+            )
+        })?;
+    }
+    let syntax_set = builder.build();
+    if let (Some(cache_dir), Some(cache_file)) = (cache_dir, cache_file) {
+        // Write to a temporary file first, so that concurrent mdcat processes
+        // never load half-written dumps.
+        let temp_file = cache_file.with_extension(format!("{}.tmp", std::process::id()));
+        let write_cache = || -> Result<(), Box<dyn Error>> {
+            fs::create_dir_all(cache_dir)?;
+            dump_to_file(&syntax_set, &temp_file)?;
+            fs::rename(&temp_file, &cache_file)?;
+            Ok(())
+        };
+        if write_cache().is_ok() {
+            remove_stale_caches(cache_dir, &cache_file);
+        } else {
+            let _ = fs::remove_file(&temp_file);
+        }
+    }
+    Ok(syntax_set)
+}
+
+/// Remove old caches of syntaxes in `cache_dir`.
+///
+/// Keep `current` and the newest other caches, up to `MAX_CACHES` in total.
+fn remove_stale_caches(cache_dir: &Path, current: &Path) {
+    let entries = match fs::read_dir(cache_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut caches = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            let is_cache = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with("syntaxes-") && name.ends_with(".packdump"))
+                .unwrap_or(false);
+            is_cache && path != current
+        })
+        .map(|path| {
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, path)
+        })
+        .collect::<Vec<_>>();
+    // Newest first
+    caches.sort_by(|a, b| b.cmp(a));
+    for (_, path) in caches.into_iter().skip(MAX_CACHES - 1) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Find all `.sublime-syntax` files beneath `dir` and add them to `files`.
+fn find_syntax_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_syntax_files(&path, files)?;
+        } else if path.extension().and_then(|extension| extension.to_str())
+            == Some("sublime-syntax")
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// A key for the cache of syntaxes merged from `files`.
+///
+/// Hash the version of mdcat as well as the path, size and modification time
+/// of every file, to rebuild the cache whenever any of these changes.
+fn cache_key(files: &[PathBuf]) -> io::Result<u64> {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    for file in files {
+        let metadata = fs::metadata(file)?;
+        file.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        metadata.modified()?.hash(&mut hasher);
+    }
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const SYNTAX: &str = "%YAML 1.2
+---
+name: Test Language
+file_extensions: [testlang]
+scope: source.testlang
+contexts:
+  main:
+    - match: '\\b(let)\\b'
+      scope: keyword.testlang
+";
+
+    #[test]
+    fn load_and_cache_syntaxes() {
+        let dir = std::env::temp_dir().join(format!("mdcat-syntaxes-{}", std::process::id()));
+        let syntax_dir = dir.join("syntaxes").join("nested");
+        let cache_dir = dir.join("cache");
+        fs::create_dir_all(&syntax_dir).unwrap();
+        fs::write(syntax_dir.join("Test.sublime-syntax"), SYNTAX).unwrap();
+
+        let find = |syntax_set: &SyntaxSet| {
+            syntax_set
+                .find_syntax_by_token("testlang")
+                .map(|syntax| syntax.name.clone())
+        };
+        let loaded = load_syntax_set(&[dir.join("syntaxes")], Some(&cache_dir)).unwrap();
+        assert_eq!(find(&loaded), Some("Test Language".to_string()));
+        assert!(loaded.find_syntax_by_token("rust").is_some());
+
+        let cache_files = fs::read_dir(&cache_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(cache_files.len(), 1);
+        let cached = from_dump_file::<SyntaxSet, _>(&cache_files[0]).unwrap();
+        assert_eq!(find(&cached), Some("Test Language".to_string()));
+        let reloaded = load_syntax_set(&[dir.join("syntaxes")], Some(&cache_dir)).unwrap();
+        assert_eq!(find(&reloaded), Some("Test Language".to_string()));
+
+        let changed = SYNTAX.replace("Test Language", "Changed Language");
+        fs::write(syntax_dir.join("Test.sublime-syntax"), changed).unwrap();
+        let changed = load_syntax_set(&[dir.join("syntaxes")], Some(&cache_dir)).unwrap();
+        assert_eq!(find(&changed), Some("Changed Language".to_string()));
+        // Keep the cache of the previous syntaxes, in case they come back
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remove_all_but_the_newest_caches() {
+        let dir = std::env::temp_dir().join(format!("mdcat-syntax-caches-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let caches = (0..6)
+            .map(|n| {
+                let cache = dir.join(format!("syntaxes-{:016x}.packdump", n));
+                fs::write(&cache, "").unwrap();
+                // Give every cache a different modification time
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                cache
+            })
+            .collect::<Vec<_>>();
+        fs::write(dir.join("unrelated"), "").unwrap();
+
+        remove_stale_caches(&dir, &caches[0]);
+        let mut remaining = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec![
+                caches[0].clone(),
+                caches[3].clone(),
+                caches[4].clone(),
+                caches[5].clone(),
+                dir.join("unrelated")
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_syntaxes_from_missing_directory() {
+        let error = load_syntax_set(&[Path::new("/no/such/syntaxes")], None).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Failed to read syntaxes from /no/such/syntaxes"));
+    }
+}
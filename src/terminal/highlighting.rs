@@ -30,6 +30,19 @@ pub fn write_as_ansi<W: Write>(
     writer: &mut W,
     ansi: &AnsiStyle,
     regions: &[(Style, &str)],
+) -> Result<()> {
+    write_as_ansi_on(writer, ansi, regions, None)
+}
+
+/// Write regions as ANSI 8-bit coloured text on the given `background`.
+///
+/// Like `write_as_ansi` but fill the background of all regions with
+/// `background` if any, instead of the default background of the terminal.
+pub fn write_as_ansi_on<W: Write>(
+    writer: &mut W,
+    ansi: &AnsiStyle,
+    regions: &[(Style, &str)],
+    background: Option<Colour>,
 ) -> Result<()> {
     for &(style, text) in regions {
         let rgb = {
@@ -61,6 +74,7 @@ pub fn write_as_ansi<W: Write>(
         ansi_style.is_bold = font.contains(FontStyle::BOLD);
         ansi_style.is_italic = font.contains(FontStyle::ITALIC);
         ansi_style.is_underline = font.contains(FontStyle::UNDERLINE);
+        ansi_style.background = background;
         ansi.write_styled(writer, &ansi_style, text)?;
     }
 
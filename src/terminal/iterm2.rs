@@ -12,8 +12,9 @@
 //! See <https://www.iterm2.com> for more information.
 
 use super::osc::write_osc;
+use super::ImageSize;
 use crate::magic;
-use crate::resources::read_url;
+use crate::resources::{read_url, HttpSettings};
 use std::error::Error;
 use std::ffi::OsStr;
 use std::io::{self, Write};
@@ -41,27 +42,36 @@ impl ITerm2Marks {
 }
 
 /// Iterm2 inline iamges.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ITerm2Images;
 
 impl ITerm2Images {
     /// Write an iterm2 inline image command to `writer`.
     ///
     /// `name` is the local file name and `contents` are the contents of the
-    /// given file.
+    /// given file.  Let iTerm2 scale the image to `size`.
     #[cfg(unix)]
     pub fn write_inline_image<W: Write, S: AsRef<OsStr>>(
         &self,
         writer: &mut W,
         name: S,
         contents: &[u8],
+        size: ImageSize,
     ) -> io::Result<()> {
         use std::os::unix::ffi::OsStrExt;
+        let mut arguments = String::new();
+        if let Some(width) = size.width {
+            arguments.push_str(&format!(";width={}px", width));
+        }
+        if let Some(height) = size.height {
+            arguments.push_str(&format!(";height={}px", height));
+        }
         write_osc(
             writer,
             &format!(
-                "1337;File=name={};inline=1:{}",
+                "1337;File=name={};inline=1{}:{}",
                 base64::encode(name.as_ref().as_bytes()),
+                arguments,
                 base64::encode(contents)
             ),
         )
@@ -73,6 +83,7 @@ impl ITerm2Images {
         _writer: &mut W,
         _name: S,
         _contents: &[u8],
+        _size: ImageSize,
     ) -> io::Result<()> {
         unimplemented!()
     }
@@ -80,9 +91,13 @@ impl ITerm2Images {
     /// Read `url` and render to an image if necessary.
     ///
     /// Render the binary content of the (rendered) image or an IO error if
-    /// reading or rendering failed.
-    pub fn read_and_render(&self, url: &Url) -> Result<Vec<u8>, Box<dyn Error>> {
-        let contents = read_url(&url)?;
+    /// reading or rendering failed.  Use `http` for remote URLs.
+    pub fn read_and_render(
+        &self,
+        url: &Url,
+        http: &HttpSettings,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let contents = read_url(&url, http)?;
         if magic::is_svg(&magic::detect_mime_type(&contents)?) {
             svg::render_svg(&contents).map_err(Into::into)
         } else {
@@ -19,8 +19,9 @@
 //!
 //! See <https://sw.kovidgoyal.net/kitty/> for more information.
 
+use super::ImageSize;
 use crate::magic;
-use crate::resources::read_url;
+use crate::resources::{read_url, HttpSettings};
 use crate::svg::render_svg;
 use image::imageops::FilterType;
 use image::ColorType;
@@ -102,7 +103,7 @@ fn get_terminal_size() -> std::io::Result<KittyDimension> {
 }
 
 /// Provides access to printing images for kitty.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct KittyImages;
 
 impl KittyImages {
@@ -181,8 +182,13 @@ impl KittyImages {
 
     /// Read the image bytes from the given URL and wrap them in a `KittyImage`.
     /// It scales the image down, if the image size exceeds the terminal window size.
-    pub fn read_and_render(&self, url: &Url) -> Result<KittyImage, Box<dyn std::error::Error>> {
-        let contents = read_url(url)?;
+    /// Use `http` for remote URLs.
+    pub fn read_and_render(
+        &self,
+        url: &Url,
+        http: &HttpSettings,
+    ) -> Result<KittyImage, Box<dyn std::error::Error>> {
+        let contents = read_url(url, http)?;
         let mime = magic::detect_mime_type(&contents)?;
         let image = if magic::is_svg(&mime) {
             image::load_from_memory(&render_svg(&contents)?)
@@ -245,8 +251,8 @@ impl KittyImages {
 
         Ok(KittyImage {
             contents: match format {
-                KittyFormat::RGB => image.into_rgb().into_raw(),
-                _ => image.into_rgba().into_raw(),
+                KittyFormat::RGB => image.into_rgb8().into_raw(),
+                _ => image.into_rgba8().into_raw(),
             },
             format,
             dimension: Some(KittyDimension {
@@ -258,13 +264,51 @@ impl KittyImages {
 }
 
 /// Holds the image bytes with its image format and dimensions.
+#[derive(Clone)]
 pub struct KittyImage {
     contents: Vec<u8>,
     format: KittyFormat,
     dimension: Option<KittyDimension>,
 }
 
+impl KittyImage {
+    /// Scale this image to `size`, but not beyond the size of the terminal.
+    ///
+    /// Return the image as it is if `size` is empty.
+    pub fn scaled(self, size: ImageSize) -> Result<KittyImage, Box<dyn Error>> {
+        if size.is_empty() {
+            return Ok(self);
+        }
+        let KittyImage {
+            contents,
+            format,
+            dimension,
+        } = self;
+        let image = match (format, dimension) {
+            (KittyFormat::RGB, Some(dimension)) => {
+                image::RgbImage::from_raw(dimension.width, dimension.height, contents)
+                    .map(DynamicImage::ImageRgb8)
+            }
+            (KittyFormat::RGBA, Some(dimension)) => {
+                image::RgbaImage::from_raw(dimension.width, dimension.height, contents)
+                    .map(DynamicImage::ImageRgba8)
+            }
+            _ => Some(image::load_from_memory(&contents)?),
+        }
+        .ok_or("Image data does not match image dimensions")?;
+        let (width, height) = size.fit(image.width(), image.height());
+        let (width, height) = get_terminal_size()?.shrink_to_fit(width, height);
+        let image = image.resize_exact(width, height, FilterType::Triangle);
+        Ok(KittyImage {
+            contents: image.into_rgba8().into_raw(),
+            format: KittyFormat::RGBA,
+            dimension: Some(KittyDimension { width, height }),
+        })
+    }
+}
+
 /// The image format (PNG, RGB or RGBA) of the image bytes.
+#[derive(Clone)]
 enum KittyFormat {
     PNG,
     RGB,
@@ -286,7 +330,52 @@ impl KittyFormat {
 }
 
 /// The dimension encapsulate the width and height in the pixel unit.
+#[derive(Clone)]
 struct KittyDimension {
     width: u32,
     height: u32,
 }
+
+impl KittyDimension {
+    /// Shrink `width` × `height` to fit into this dimension.
+    ///
+    /// Keep the aspect ratio, and return sizes which already fit as they are.
+    fn shrink_to_fit(&self, width: u32, height: u32) -> (u32, u32) {
+        let (width, height) = if self.width < width {
+            ImageSize {
+                width: Some(self.width),
+                height: None,
+            }
+            .fit(width, height)
+        } else {
+            (width, height)
+        };
+        if self.height < height {
+            ImageSize {
+                width: None,
+                height: Some(self.height),
+            }
+            .fit(width, height)
+        } else {
+            (width, height)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn shrink_to_fit_terminal() {
+        let terminal = KittyDimension {
+            width: 800,
+            height: 600,
+        };
+        assert_eq!(terminal.shrink_to_fit(400, 200), (400, 200));
+        assert_eq!(terminal.shrink_to_fit(1600, 400), (800, 200));
+        assert_eq!(terminal.shrink_to_fit(400, 1200), (200, 600));
+        assert_eq!(terminal.shrink_to_fit(3200, 1200), (800, 300));
+    }
+}
@@ -18,6 +18,8 @@ mod osc;
 mod terminology;
 
 pub use self::ansi::AnsiStyle;
+pub(crate) use self::kitty::KittyImage;
+pub use self::osc::OSC52Clipboard;
 pub use self::size::Size as TerminalSize;
 
 /// The capability of basic styling.
@@ -62,6 +64,52 @@ pub enum ImageCapability {
     Kitty(self::kitty::KittyImages),
 }
 
+/// The capability of the terminal to copy text to the clipboard.
+#[derive(Debug)]
+pub enum ClipboardCapability {
+    /// The terminal can't copy text to the clipboard.
+    None,
+    /// The terminal supports [OSC 52] to copy text to the clipboard.
+    ///
+    /// [OSC 52]: https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Operating-System-Commands
+    OSC52(self::osc::OSC52Clipboard),
+}
+
+/// The size to show an image at, in pixels.
+///
+/// Documents may give a width, a height, or both, e.g. with the `width` and
+/// `height` attributes of HTML images.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ImageSize {
+    /// The width of the image.
+    pub width: Option<u32>,
+    /// The height of the image.
+    pub height: Option<u32>,
+}
+
+impl ImageSize {
+    /// Whether this size gives neither a width nor a height.
+    pub fn is_empty(self) -> bool {
+        self.width.is_none() && self.height.is_none()
+    }
+
+    /// Fit an image of `width` × `height` pixels to this size.
+    ///
+    /// If this size only gives a width or a height, scale the other dimension
+    /// to keep the aspect ratio of the image.
+    pub fn fit(self, width: u32, height: u32) -> (u32, u32) {
+        let scale = |value: u32, to: u32, from: u32| {
+            (u64::from(value) * u64::from(to) / u64::from(from.max(1))).max(1) as u32
+        };
+        match (self.width, self.height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, scale(height, w, width)),
+            (None, Some(h)) => (scale(width, h, height), h),
+            (None, None) => (width, height),
+        }
+    }
+}
+
 /// The capabilities of a terminal.
 #[derive(Debug)]
 pub struct TerminalCapabilities {
@@ -75,6 +123,8 @@ pub struct TerminalCapabilities {
     pub image: ImageCapability,
     /// How the terminal supports marks.
     pub marks: MarkCapability,
+    /// How the terminal supports copying to the clipboard.
+    pub clipboard: ClipboardCapability,
 }
 
 /// Get the version of the underlying VTE terminal if any.
@@ -88,6 +138,17 @@ fn get_vte_version() -> Option<(u8, u8)> {
     })
 }
 
+/// Detect whether the terminal supports OSC 52 to copy to the clipboard.
+///
+/// See `osc::supports_osc52` for the terminals we detect.
+fn detect_clipboard() -> ClipboardCapability {
+    if self::osc::supports_osc52(|name| std::env::var(name).ok()) {
+        ClipboardCapability::OSC52(self::osc::OSC52Clipboard)
+    } else {
+        ClipboardCapability::None
+    }
+}
+
 impl TerminalCapabilities {
     /// A terminal which supports nothing.
     pub fn none() -> TerminalCapabilities {
@@ -97,6 +158,7 @@ impl TerminalCapabilities {
             links: LinkCapability::None,
             image: ImageCapability::None,
             marks: MarkCapability::None,
+            clipboard: ClipboardCapability::None,
         }
     }
 
@@ -108,6 +170,7 @@ impl TerminalCapabilities {
             links: LinkCapability::None,
             image: ImageCapability::None,
             marks: MarkCapability::None,
+            clipboard: ClipboardCapability::None,
         }
     }
 
@@ -120,6 +183,7 @@ impl TerminalCapabilities {
                 links: LinkCapability::OSC8(self::osc::OSC8Links::for_localhost()),
                 image: ImageCapability::ITerm2(self::iterm2::ITerm2Images),
                 marks: MarkCapability::ITerm2(self::iterm2::ITerm2Marks),
+                clipboard: ClipboardCapability::OSC52(self::osc::OSC52Clipboard),
             }
         } else if self::terminology::is_terminology() {
             TerminalCapabilities {
@@ -128,6 +192,7 @@ impl TerminalCapabilities {
                 links: LinkCapability::OSC8(self::osc::OSC8Links::for_localhost()),
                 image: ImageCapability::Terminology(self::terminology::TerminologyImages),
                 marks: MarkCapability::None,
+                clipboard: ClipboardCapability::None,
             }
         } else if self::kitty::is_kitty() {
             TerminalCapabilities {
@@ -136,6 +201,7 @@ impl TerminalCapabilities {
                 links: LinkCapability::None,
                 image: ImageCapability::Kitty(self::kitty::KittyImages),
                 marks: MarkCapability::None,
+                clipboard: ClipboardCapability::OSC52(self::osc::OSC52Clipboard),
             }
         } else if get_vte_version().filter(|&v| v >= (50, 0)).is_some() {
             TerminalCapabilities {
@@ -144,9 +210,39 @@ impl TerminalCapabilities {
                 links: LinkCapability::OSC8(self::osc::OSC8Links::for_localhost()),
                 image: ImageCapability::None,
                 marks: MarkCapability::None,
+                clipboard: detect_clipboard(),
             }
         } else {
-            TerminalCapabilities::ansi()
+            TerminalCapabilities {
+                clipboard: detect_clipboard(),
+                ..TerminalCapabilities::ansi()
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn fit_image_size() {
+        let width = ImageSize {
+            width: Some(100),
+            height: None,
+        };
+        assert_eq!(width.fit(400, 200), (100, 50));
+        let height = ImageSize {
+            width: None,
+            height: Some(100),
+        };
+        assert_eq!(height.fit(400, 200), (200, 100));
+        let both = ImageSize {
+            width: Some(10),
+            height: Some(20),
+        };
+        assert_eq!(both.fit(400, 200), (10, 20));
+        assert_eq!(ImageSize::default().fit(400, 200), (400, 200));
+    }
+}
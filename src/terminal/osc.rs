@@ -18,6 +18,40 @@ pub fn write_osc<W: Write>(writer: &mut W, command: &str) -> Result<()> {
     Ok(())
 }
 
+/// Access to the clipboard with [OSC 52].
+///
+/// [OSC 52]: https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Operating-System-Commands
+#[derive(Debug)]
+pub struct OSC52Clipboard;
+
+impl OSC52Clipboard {
+    /// Copy `contents` to the clipboard.
+    pub fn copy<W: Write>(&self, writer: &mut W, contents: &str) -> Result<()> {
+        write_osc(writer, &format!("52;c;{}", base64::encode(contents)))
+    }
+}
+
+/// Whether the terminal supports OSC 52, judging by environment variables.
+///
+/// Get the value of environment variables from `var`.  Detect
+///
+/// * xterm by `XTERM_VERSION`,
+/// * WezTerm by `TERM_PROGRAM`,
+/// * Alacritty and foot by `TERM`,
+/// * and tmux by `TERM_PROGRAM` or `TERM`.  tmux only passes OSC 52 on to the
+///   clipboard if its `set-clipboard` option is `on`.
+///
+/// We detect iTerm2 and kitty, which support OSC 52 as well, separately.
+pub fn supports_osc52<F: Fn(&str) -> Option<String>>(var: F) -> bool {
+    let term = var("TERM").unwrap_or_default();
+    let term_program = var("TERM_PROGRAM").unwrap_or_default();
+    var("XTERM_VERSION").is_some()
+        || matches!(term_program.as_str(), "WezTerm" | "tmux")
+        || term == "alacritty"
+        || term.starts_with("foot")
+        || term.starts_with("tmux")
+}
+
 #[derive(Debug)]
 pub struct OSC8Links {
     hostname: String,
@@ -114,4 +148,39 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn detect_osc52_support() {
+        let supports = |vars: &[(&str, &str)]| {
+            super::supports_osc52(|name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            })
+        };
+        assert!(supports(&[
+            ("XTERM_VERSION", "XTerm(353)"),
+            ("TERM", "xterm")
+        ]));
+        assert!(supports(&[("TERM_PROGRAM", "WezTerm")]));
+        assert!(supports(&[("TERM", "alacritty")]));
+        assert!(supports(&[("TERM", "foot-extra")]));
+        assert!(supports(&[("TERM", "tmux-256color")]));
+        assert!(supports(&[("TERM", "screen"), ("TERM_PROGRAM", "tmux")]));
+        assert!(!supports(&[("TERM", "xterm-256color")]));
+        assert!(!supports(&[("TERM", "linux")]));
+        assert!(!supports(&[]));
+    }
+
+    #[test]
+    fn copy_to_clipboard() {
+        let mut sink = Vec::new();
+        super::OSC52Clipboard
+            .copy(&mut sink, "echo hello\n")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "\x1b]52;c;ZWNobyBoZWxsbwo=\x07"
+        );
+    }
 }
@@ -16,7 +16,7 @@
 //!
 //! [Terminology]: http://terminolo.gy
 
-use super::TerminalSize;
+use super::{ImageSize, TerminalSize};
 use std::io::{Result, Write};
 use url::Url;
 
@@ -38,6 +38,7 @@ impl TerminologyImages {
         writer: &mut W,
         max_size: TerminalSize,
         url: &Url,
+        size: ImageSize,
     ) -> Result<()> {
         // Terminology escape sequence is like: set texture to path, then draw a
         // rectangle of chosen character to be replaced by the given texture.
@@ -50,13 +51,16 @@ impl TerminologyImages {
         //
         // We need to compute image proportion to draw the appropriate
         // rectangle. If we can't compute the image proportion (e.g. it's an
-        // external URL), we fallback to a rectangle that is half of the screen.
+        // external URL), we take the proportion from `size`, and fallback to a
+        // rectangle that is half of the screen.
         let columns = max_size.width;
 
         let lines = Some(url)
             .filter(|url| url.scheme() == "file")
             .and_then(|url| url.to_file_path().ok())
             .and_then(|path| image::image_dimensions(path).ok())
+            .map(|(width, height)| size.fit(width, height))
+            .or_else(|| size.width.zip(size.height))
             .map(|(width, height)| {
                 let (w, h) = (f64::from(width), f64::from(height));
                 // We divide by 2 because terminal cursor/font most likely has a
@@ -0,0 +1,707 @@
+// Copyright 2020 Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Headings and tables of contents.
+
+use crate::terminal::StyleCapability;
+use crate::Settings;
+use ansi_term::{Colour, Style};
+use pulldown_cmark::Event::*;
+use pulldown_cmark::{Event, Tag};
+use regex::Regex;
+use serde_json::json;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{Result, Write};
+use std::ops::Range;
+
+/// A heading in a markdown document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    /// The level of the heading, from 1 to 6.
+    pub level: u32,
+    /// The text of the heading, without any formatting.
+    pub text: String,
+    /// The anchor of the heading.
+    ///
+    /// The anchor GitHub generates for this heading, to link to the heading
+    /// with `#anchor`.
+    pub anchor: String,
+    /// The position of the heading in the document.
+    ///
+    /// This is the index of the event which starts the heading.
+    pub position: usize,
+    /// The section number of the heading.
+    ///
+    /// The number of the heading and all of its parent headings, from level
+    /// 1, e.g. `[1, 2]` for the second level 2 heading in the first chapter.
+    pub number: Vec<u32>,
+}
+
+/// Count headings to number them.
+#[derive(Debug, Default)]
+pub struct HeadingCounter {
+    /// The current number of each heading level.
+    counters: [u32; 6],
+}
+
+impl HeadingCounter {
+    /// Count a heading of the given `level` and return its section number.
+    ///
+    /// Reset the numbers of all lower levels.  Count missing parent levels as 0,
+    /// e.g. a level 3 heading right after the first level 1 heading gets
+    /// `[1, 0, 1]`.
+    pub fn count(&mut self, level: u32) -> Vec<u32> {
+        let level = (level as usize).max(1).min(self.counters.len());
+        self.counters[level - 1] += 1;
+        for counter in &mut self.counters[level..] {
+            *counter = 0;
+        }
+        self.counters[..level].to_vec()
+    }
+}
+
+/// How to number headings.
+#[derive(Debug, Clone)]
+pub struct HeadingNumbering {
+    /// The first heading level to number.
+    ///
+    /// Headings above this level get no number, and numbers omit the counts of
+    /// those levels; they still restart the counts below them though, e.g. with
+    /// 2 the first level 2 heading under the second level 1 heading gets 1.
+    pub start_level: u32,
+    /// The format of a heading number.
+    ///
+    /// Replace `{}` with the numbers of all levels, joined by `.`, e.g.
+    /// `{}.` gives `1.2.`.
+    pub format: String,
+}
+
+impl Default for HeadingNumbering {
+    /// Number all headings, like `1.2.3`.
+    fn default() -> HeadingNumbering {
+        HeadingNumbering {
+            start_level: 1,
+            format: "{}".to_string(),
+        }
+    }
+}
+
+impl HeadingNumbering {
+    /// Format the given section `number` of a heading.
+    ///
+    /// Return `None` if the heading is below the start level and thus has no
+    /// number.
+    pub fn format(&self, number: &[u32]) -> Option<String> {
+        let start = self.start_level.max(1) as usize;
+        if number.len() < start {
+            None
+        } else {
+            let number = number[start - 1..]
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(".");
+            Some(self.format.replace("{}", &number))
+        }
+    }
+}
+
+/// Turn heading `text` into an anchor like GitHub does.
+///
+/// Lowercase `text`, drop all punctuation except `-` and `_`, and replace
+/// spaces with `-`.
+fn slug(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Compute the table of contents of a document from its `events`.
+///
+/// Return all headings in the order of the document.  Assign every heading a
+/// unique anchor: Like GitHub, number all but the first heading with the same
+/// text, e.g. `#usage`, `#usage-1`, `#usage-2`, etc.
+pub fn table_of_contents<'a, 'e: 'a, I>(events: I) -> Vec<Heading>
+where
+    I: IntoIterator<Item = &'a Event<'e>>,
+{
+    let mut headings = Vec::new();
+    let mut anchors = HashSet::new();
+    let mut counter = HeadingCounter::default();
+    let mut current: Option<(u32, usize, String)> = None;
+    for (position, event) in events.into_iter().enumerate() {
+        match (event, &mut current) {
+            (Start(Tag::Heading(level)), None) => current = Some((*level, position, String::new())),
+            (Text(text), Some((_, _, heading_text))) | (Code(text), Some((_, _, heading_text))) => {
+                heading_text.push_str(text)
+            }
+            (End(Tag::Heading(_)), Some(_)) => {
+                if let Some((level, position, text)) = current.take() {
+                    let base = slug(&text);
+                    let anchor = (0..)
+                        .map(|n| match n {
+                            0 => base.clone(),
+                            n => format!("{}-{}", base, n),
+                        })
+                        .find(|anchor| !anchors.contains(anchor))
+                        .unwrap();
+                    anchors.insert(anchor.clone());
+                    headings.push(Heading {
+                        level,
+                        text,
+                        anchor,
+                        position,
+                        number: counter.count(level),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    headings
+}
+
+/// A pattern to select a section of a document by its heading.
+#[derive(Debug, Clone)]
+pub enum SectionPattern {
+    /// Match headings which contain the given text, ignoring case.
+    Substring(String),
+    /// Match headings which match the given regular expression.
+    Regex(Regex),
+    /// Match headings whose anchor equals the anchor of the given text.
+    ///
+    /// Ignore a leading `#`, to match `#installation` just like `Installation`.
+    Anchor(String),
+}
+
+impl SectionPattern {
+    /// Whether this pattern matches the given `heading`.
+    pub fn matches(&self, heading: &Heading) -> bool {
+        match self {
+            SectionPattern::Substring(text) => {
+                heading.text.to_lowercase().contains(&text.to_lowercase())
+            }
+            SectionPattern::Regex(regex) => regex.is_match(&heading.text),
+            SectionPattern::Anchor(text) => heading.anchor == slug(text.trim_start_matches('#')),
+        }
+    }
+}
+
+impl fmt::Display for SectionPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SectionPattern::Substring(text) | SectionPattern::Anchor(text) => write!(f, "{}", text),
+            SectionPattern::Regex(regex) => write!(f, "{}", regex),
+        }
+    }
+}
+
+/// Find a section in a document.
+///
+/// Find the first heading in `events` which matches `pattern`, and return the
+/// range of events from this heading up to the next heading of the same or a
+/// higher level, or up to the end of the document.
+///
+/// If the heading is inside a container, like a list item or a block quote,
+/// end the section at the end of this container at the latest, to keep the
+/// events of the section balanced.
+///
+/// Return `None` if no heading matches `pattern`.
+pub fn find_section<'a, 'e: 'a, I>(events: I, pattern: &SectionPattern) -> Option<Range<usize>>
+where
+    I: IntoIterator<Item = &'a Event<'e>>,
+{
+    let events = events.into_iter().collect::<Vec<_>>();
+    let headings = table_of_contents(events.iter().copied());
+    let heading = headings.iter().find(|heading| pattern.matches(heading))?;
+    let mut depth = 0_usize;
+    let end = events
+        .iter()
+        .enumerate()
+        .skip(heading.position)
+        .find_map(|(index, event)| match event {
+            Start(Tag::Heading(level))
+                if depth == 0 && index > heading.position && *level <= heading.level =>
+            {
+                Some(index)
+            }
+            Start(_) => {
+                depth += 1;
+                None
+            }
+            // The end of the container of the heading
+            End(_) if depth == 0 => Some(index),
+            End(_) => {
+                depth -= 1;
+                None
+            }
+            _ => None,
+        })
+        .unwrap_or(events.len());
+    Some(heading.position..end)
+}
+
+/// Find the heading an internal link `destination` points to.
+///
+/// Return the heading in `headings` whose anchor equals the fragment of
+/// `destination`, or `None` if `destination` is no internal link like
+/// `#anchor`, or if no heading has this anchor.
+pub fn find_heading<'h>(headings: &'h [Heading], destination: &str) -> Option<&'h Heading> {
+    destination
+        .strip_prefix('#')
+        .and_then(|anchor| headings.iter().find(|heading| heading.anchor == anchor))
+}
+
+/// Find internal links which point to no heading.
+///
+/// Return the destinations of all links in `events` like `#anchor` whose anchor
+/// belongs to none of the `headings` of the document, in the order of the
+/// document.
+///
+/// `events` may be a section of the document, as long as `headings` has all
+/// headings of the whole document.
+pub fn dangling_anchors<'a, 'e: 'a, I>(events: I, headings: &[Heading]) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a Event<'e>>,
+{
+    events
+        .into_iter()
+        .filter_map(|event| match event {
+            Start(Tag::Link(_, destination, _))
+                if destination.starts_with('#')
+                    && find_heading(headings, destination).is_none() =>
+            {
+                Some(destination.to_string())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The format of an outline.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutlineFormat {
+    /// A human-readable outline of headings, indented by level.
+    Text,
+    /// A JSON array with an object for each heading.
+    Json,
+}
+
+/// Get the line number of `offset` in `source`.
+fn line_number(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+/// Write the outline of a document to `writer`.
+///
+/// `events` are the events of the document along with their offsets into the
+/// `source` of the document, as returned by `Parser::into_offset_iter`.
+///
+/// Write every heading with its level and its line number in `source`, in the
+/// given `format`.  Use `settings` to style a text outline, and to number
+/// headings.
+pub fn write_outline<'e, W: Write>(
+    writer: &mut W,
+    settings: &Settings,
+    source: &str,
+    events: &[(Event<'e>, Range<usize>)],
+    format: OutlineFormat,
+) -> Result<()> {
+    let headings = table_of_contents(events.iter().map(|(event, _)| event));
+    let lines = headings
+        .iter()
+        .map(|heading| line_number(source, events[heading.position].1.start))
+        .collect::<Vec<_>>();
+    let numbers = headings
+        .iter()
+        .map(|heading| {
+            settings
+                .heading_numbering
+                .as_ref()
+                .and_then(|numbering| numbering.format(&heading.number))
+        })
+        .collect::<Vec<_>>();
+    match format {
+        OutlineFormat::Text => {
+            let width = lines.last().map_or(0, |line| line.to_string().len());
+            let style = Style::new().fg(Colour::Blue).bold();
+            for ((heading, line), number) in headings.iter().zip(lines).zip(numbers) {
+                let indent = " ".repeat(2 * (heading.level as usize - 1));
+                write!(writer, "{:>width$}  {}", line, indent, width = width)?;
+                let number = number.map(|number| number + " ").unwrap_or_default();
+                let text = format!(
+                    "{}{}{}",
+                    "\u{2504}".repeat(heading.level as usize),
+                    number,
+                    heading.text
+                );
+                match settings.terminal_capabilities.style {
+                    StyleCapability::None => write!(writer, "{}", text)?,
+                    StyleCapability::Ansi(ref ansi) => ansi.write_styled(writer, &style, text)?,
+                }
+                writeln!(writer)?;
+            }
+        }
+        OutlineFormat::Json => {
+            let outline = headings
+                .iter()
+                .zip(lines)
+                .zip(numbers)
+                .map(|((heading, line), number)| {
+                    let mut entry = json!({
+                        "level": heading.level,
+                        "text": heading.text,
+                        "anchor": heading.anchor,
+                        "line": line,
+                    });
+                    if let Some(number) = number {
+                        entry["number"] = json!(number);
+                    }
+                    entry
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_writer_pretty(&mut *writer, &outline)?;
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use pulldown_cmark::Parser;
+
+    fn toc(markdown: &str) -> Vec<Heading> {
+        table_of_contents(&Parser::new(markdown).collect::<Vec<_>>())
+    }
+
+    fn outline(markdown: &str, format: OutlineFormat) -> String {
+        outline_with_numbering(markdown, format, None)
+    }
+
+    fn outline_with_numbering(
+        markdown: &str,
+        format: OutlineFormat,
+        heading_numbering: Option<HeadingNumbering>,
+    ) -> String {
+        let settings = Settings {
+            heading_numbering,
+            ..Settings::default()
+        };
+        let events = Parser::new(markdown).into_offset_iter().collect::<Vec<_>>();
+        let mut sink = Vec::new();
+        write_outline(&mut sink, &settings, markdown, &events, format).unwrap();
+        String::from_utf8(sink).unwrap()
+    }
+
+    const OUTLINE_DOCUMENT: &str = "# Title
+
+Text
+
+## Usage
+
+Lorem ipsum
+dolor sit amet
+
+### Options
+
+Setext heading
+--------------
+";
+
+    #[test]
+    fn text_outline() {
+        assert_eq!(
+            outline(OUTLINE_DOCUMENT, OutlineFormat::Text),
+            " 1  \u{2504}Title
+ 5    \u{2504}\u{2504}Usage
+10      \u{2504}\u{2504}\u{2504}Options
+12    \u{2504}\u{2504}Setext heading
+"
+        );
+    }
+
+    #[test]
+    fn numbered_text_outline() {
+        let numbering = HeadingNumbering {
+            start_level: 2,
+            format: "{}.".to_string(),
+        };
+        assert_eq!(
+            outline_with_numbering(OUTLINE_DOCUMENT, OutlineFormat::Text, Some(numbering)),
+            " 1  \u{2504}Title
+ 5    \u{2504}\u{2504}1. Usage
+10      \u{2504}\u{2504}\u{2504}1.1. Options
+12    \u{2504}\u{2504}2. Setext heading
+"
+        );
+    }
+
+    #[test]
+    fn numbered_json_outline() {
+        let outline = outline_with_numbering(
+            OUTLINE_DOCUMENT,
+            OutlineFormat::Json,
+            Some(HeadingNumbering::default()),
+        );
+        let value = serde_json::from_str::<serde_json::Value>(&outline).unwrap();
+        let numbers = value
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["number"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, vec!["1", "1.1", "1.1.1", "1.2"]);
+    }
+
+    #[test]
+    fn find_heading_by_anchor() {
+        let events = Parser::new("# Usage\n\n## Options\n").collect::<Vec<_>>();
+        let headings = table_of_contents(&events);
+        assert_eq!(
+            find_heading(&headings, "#options").map(|heading| heading.text.as_str()),
+            Some("Options")
+        );
+        assert_eq!(find_heading(&headings, "#missing"), None);
+        assert_eq!(find_heading(&headings, "options"), None);
+    }
+
+    #[test]
+    fn find_dangling_anchors() {
+        let events = Parser::new(
+            "# Usage
+
+See [options](#options), [usage](#usage), [nothing](#nothing) and
+[elsewhere](README.md#options).
+",
+        )
+        .collect::<Vec<_>>();
+        let headings = table_of_contents(&events);
+        assert_eq!(
+            dangling_anchors(&events, &headings),
+            vec!["#options".to_string(), "#nothing".to_string()]
+        );
+    }
+
+    #[test]
+    fn find_dangling_anchors_in_section() {
+        let events = Parser::new(
+            "# Usage
+
+See [options](#options).
+
+# Options
+
+See [usage](#usage) and [nothing](#nothing).
+",
+        )
+        .collect::<Vec<_>>();
+        let range = find_section(&events, &SectionPattern::Anchor("options".to_string())).unwrap();
+        assert_eq!(
+            dangling_anchors(&events[range], &table_of_contents(&events)),
+            vec!["#nothing".to_string()]
+        );
+    }
+
+    #[test]
+    fn count_headings() {
+        let mut counter = HeadingCounter::default();
+        assert_eq!(counter.count(1), vec![1]);
+        assert_eq!(counter.count(3), vec![1, 0, 1]);
+        assert_eq!(counter.count(2), vec![1, 1]);
+        assert_eq!(counter.count(3), vec![1, 1, 1]);
+        assert_eq!(counter.count(3), vec![1, 1, 2]);
+        assert_eq!(counter.count(1), vec![2]);
+        assert_eq!(counter.count(2), vec![2, 1]);
+    }
+
+    #[test]
+    fn format_heading_numbers() {
+        let numbering = HeadingNumbering::default();
+        assert_eq!(numbering.format(&[1, 2, 3]), Some("1.2.3".to_string()));
+        let numbering = HeadingNumbering {
+            start_level: 2,
+            format: "\u{a7}{}".to_string(),
+        };
+        assert_eq!(numbering.format(&[1]), None);
+        assert_eq!(numbering.format(&[1, 2, 3]), Some("\u{a7}2.3".to_string()));
+    }
+
+    #[test]
+    fn json_outline() {
+        let outline = outline(OUTLINE_DOCUMENT, OutlineFormat::Json);
+        let value = serde_json::from_str::<serde_json::Value>(&outline).unwrap();
+        assert_eq!(
+            value,
+            json!([
+                {"level": 1, "text": "Title", "anchor": "title", "line": 1},
+                {"level": 2, "text": "Usage", "anchor": "usage", "line": 5},
+                {"level": 3, "text": "Options", "anchor": "options", "line": 10},
+                {"level": 2, "text": "Setext heading", "anchor": "setext-heading", "line": 12},
+            ])
+        );
+    }
+
+    const SECTION_DOCUMENT: &str = "# mdcat
+
+Intro
+
+## Installation
+
+Install [it](https://example.com).
+
+### From source
+
+Build it.
+
+## Usage
+
+Use it.
+";
+
+    fn section(pattern: SectionPattern) -> Option<Vec<Event<'static>>> {
+        let events = Parser::new(SECTION_DOCUMENT).collect::<Vec<_>>();
+        find_section(&events, &pattern).map(|range| events[range].to_vec())
+    }
+
+    #[test]
+    fn find_section_by_substring() {
+        let events = section(SectionPattern::Substring("INSTALL".to_string())).unwrap();
+        assert_eq!(events.first(), Some(&Start(Tag::Heading(2))));
+        assert_eq!(events.last(), Some(&End(Tag::Paragraph)));
+        assert!(events.contains(&Text("Build it.".into())));
+        assert!(!events.contains(&Text("Usage".into())));
+    }
+
+    #[test]
+    fn find_section_by_regex() {
+        let events = section(SectionPattern::Regex(Regex::new("^(Install|Use)").unwrap())).unwrap();
+        assert!(events.contains(&Text("Install ".into())));
+        assert!(!events.contains(&Text("Use it.".into())));
+    }
+
+    #[test]
+    fn find_section_by_anchor() {
+        let events = section(SectionPattern::Anchor("#from-source".to_string())).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                Start(Tag::Heading(3)),
+                Text("From source".into()),
+                End(Tag::Heading(3)),
+                Start(Tag::Paragraph),
+                Text("Build it.".into()),
+                End(Tag::Paragraph),
+            ]
+        );
+    }
+
+    #[test]
+    fn find_section_at_end_of_document() {
+        let events = section(SectionPattern::Anchor("Usage".to_string())).unwrap();
+        assert_eq!(events.last(), Some(&End(Tag::Paragraph)));
+        assert!(events.contains(&Text("Use it.".into())));
+    }
+
+    #[test]
+    fn find_section_in_container() {
+        let events = Parser::new(
+            "> # Quoted
+>
+> In the quote
+
+After the quote
+
+- # Listed
+  In the item
+- Next item
+",
+        )
+        .collect::<Vec<_>>();
+        let find = |text: &str| {
+            let range = find_section(&events, &SectionPattern::Substring(text.to_string()));
+            range.map(|range| events[range].to_vec()).unwrap()
+        };
+
+        let quoted = find("Quoted");
+        assert_eq!(quoted.first(), Some(&Start(Tag::Heading(1))));
+        assert_eq!(quoted.last(), Some(&End(Tag::Paragraph)));
+        assert!(quoted.contains(&Text("In the quote".into())));
+        assert!(!quoted.contains(&Text("After the quote".into())));
+
+        let listed = find("Listed");
+        assert_eq!(listed.last(), Some(&Text("In the item".into())));
+        assert!(!listed.contains(&Text("Next item".into())));
+    }
+
+    #[test]
+    fn find_section_without_match() {
+        assert_eq!(
+            section(SectionPattern::Substring("License".to_string())),
+            None
+        );
+    }
+
+    #[test]
+    fn slug_like_github() {
+        assert_eq!(slug("Installation"), "installation");
+        assert_eq!(slug("Building with rustup"), "building-with-rustup");
+        assert_eq!(slug("3rd party packages"), "3rd-party-packages");
+        assert_eq!(slug("What's new in v0.16.1?"), "whats-new-in-v0161");
+        assert_eq!(slug("foo_bar - baz"), "foo_bar---baz");
+        assert_eq!(slug("Über Änderungen"), "über-änderungen");
+    }
+
+    #[test]
+    fn table_of_contents_of_document() {
+        let headings = toc("# Title\n\nSome text\n\n## The `foo` *command*\n\n### Usage\n");
+        assert_eq!(
+            headings,
+            vec![
+                Heading {
+                    level: 1,
+                    text: "Title".to_string(),
+                    anchor: "title".to_string(),
+                    position: 0,
+                    number: vec![1],
+                },
+                Heading {
+                    level: 2,
+                    text: "The foo command".to_string(),
+                    anchor: "the-foo-command".to_string(),
+                    position: 6,
+                    number: vec![1, 1],
+                },
+                Heading {
+                    level: 3,
+                    text: "Usage".to_string(),
+                    anchor: "usage".to_string(),
+                    position: 14,
+                    number: vec![1, 1, 1],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn table_of_contents_with_duplicate_headings() {
+        let anchors = toc("# Usage\n\n## Usage\n\n## Usage-1\n\n## Usage\n")
+            .into_iter()
+            .map(|heading| heading.anchor)
+            .collect::<Vec<_>>();
+        assert_eq!(anchors, vec!["usage", "usage-1", "usage-1-1", "usage-2"]);
+    }
+}
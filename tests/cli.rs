@@ -69,6 +69,21 @@ mod cli {
         assert!(output.stdout.is_empty());
     }
 
+    #[test]
+    fn remote_document_fails_with_local_only() {
+        let output = run_cargo_mdcat(&["--local", "https://example.com/README.md"]);
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        assert!(!output.status.success());
+        assert!(
+            stderr.contains(
+                "Error: https://example.com/README.md: Remote documents not permitted with --local"
+            ),
+            "Stderr: {}",
+            stderr
+        );
+        assert!(output.stdout.is_empty());
+    }
+
     #[test]
     fn ignore_broken_pipe() {
         let mut child = Command::new("cargo")
@@ -23,7 +23,6 @@ use std::path::Path;
 
 use lazy_static::lazy_static;
 use pulldown_cmark::{Options, Parser};
-use syntect::parsing::SyntaxSet;
 use test_generator::test_resources;
 
 lazy_static! {
@@ -31,9 +30,7 @@ lazy_static! {
     // and doing it for every test again causes a nasty drop in execution speed.
     static ref SETTINGS: mdcat::Settings = mdcat::Settings {
         terminal_capabilities: mdcat::TerminalCapabilities::ansi(),
-        terminal_size: mdcat::TerminalSize::default(),
-        resource_access: mdcat::ResourceAccess::LocalOnly,
-        syntax_set: SyntaxSet::load_defaults_newlines(),
+        ..mdcat::Settings::default()
     };
 }
 